@@ -0,0 +1,27 @@
+use nalgebra::Vector2;
+use rapier2d::prelude::*;
+
+/* Input that the physics thread applies at the start of its next step, in the order it was queued. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Spawn {
+        x: f32,
+        y: f32,
+    },
+    Remove {
+        x: f32,
+        y: f32,
+    },
+    /* Applies `impulse` to whichever particle is under (x, y). */
+    Impulse {
+        x: f32,
+        y: f32,
+        impulse: Vector2<Real>,
+    },
+    SetGravity {
+        gravity: Vector2<Real>,
+    },
+    /* Empties the box, as `State::reset` does. */
+    Clear,
+    TogglePause,
+}
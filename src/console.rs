@@ -0,0 +1,94 @@
+use rapier2d::prelude::vector;
+use raylib::prelude::*;
+
+use crate::{command::Command, state::State};
+
+const HELP: &str = "gravity <x> <y> | spawn <x> <y> | clear | pause";
+
+/* A one-line prompt, opened with the backtick key, that turns typed lines into queued commands. */
+#[derive(Default)]
+pub struct Console {
+    open: bool,
+    line: String,
+    /* The outcome of the last submitted line. */
+    reply: String,
+}
+
+impl Console {
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /* Takes this frame's typing while open; the rest of the input is left for the caller.
+     * `view` is the world point under the window's top-left, as for the cursor. */
+    pub fn update(&mut self, rl: &mut RaylibHandle, state: &State, view: (f32, f32)) {
+        if rl.is_key_pressed(KeyboardKey::KEY_GRAVE) {
+            self.open = !self.open;
+        }
+        /* Drain queued characters even while closed, so keys typed before opening don't leak in. */
+        while let Some(c) = rl.get_char_pressed() {
+            if self.open && c != '`' && !c.is_control() {
+                self.line.push(c);
+            }
+        }
+        if !self.open {
+            return;
+        }
+
+        if rl.is_key_pressed(KeyboardKey::KEY_BACKSPACE) {
+            self.line.pop();
+        }
+        if rl.is_key_pressed(KeyboardKey::KEY_ENTER) {
+            let line = std::mem::take(&mut self.line);
+            self.reply = match parse(&line, view) {
+                Ok(Some(command)) => {
+                    state.enqueue_command(command);
+                    format!("> {line}")
+                }
+                Ok(None) => String::new(),
+                Err(message) => message,
+            };
+        }
+    }
+
+    pub fn draw(&self, d: &mut impl RaylibDraw, width: i32, height: i32) {
+        if !self.open {
+            return;
+        }
+        d.draw_rectangle(0, height - 28, width, 28, Color::BLACK.fade(0.7));
+        d.draw_text(&self.reply, 4, height - 26, 10, Color::LIGHTGRAY);
+        d.draw_text(
+            &format!("`{}_", self.line),
+            4,
+            height - 13,
+            10,
+            Color::WHITE,
+        );
+    }
+}
+
+/* Positions are relative to the window's top-left, like the cursor. `Ok(None)` for a blank line. */
+fn parse(line: &str, (view_x, view_y): (f32, f32)) -> Result<Option<Command>, String> {
+    let mut words = line.split_whitespace();
+    let Some(name) = words.next() else {
+        return Ok(None);
+    };
+    let args: Vec<f32> = words
+        .map(|word| word.parse().map_err(|_| format!("not a number: {word}")))
+        .collect::<Result<_, _>>()?;
+
+    let command = match (name, args.as_slice()) {
+        ("gravity", [x, y]) => Command::SetGravity {
+            gravity: vector![*x, *y],
+        },
+        ("spawn", [x, y]) => Command::Spawn {
+            x: view_x + x,
+            y: view_y + y,
+        },
+        ("clear", []) => Command::Clear,
+        ("pause", []) => Command::TogglePause,
+        ("help", []) => return Err(HELP.to_string()),
+        _ => return Err(format!("unknown command: {line}  ({HELP})")),
+    };
+    Ok(Some(command))
+}
@@ -0,0 +1,43 @@
+use std::{error::Error, fmt, io};
+
+use rapier2d::prelude::RigidBodyHandle;
+
+#[derive(Debug)]
+pub enum SandboxError {
+    Io(io::Error),
+    /* Text that isn't in the format `Scene::to_text` writes. */
+    InvalidScene,
+    /* The handle doesn't refer to a body that is still in the sandbox. */
+    StaleHandle(RigidBodyHandle),
+    /* The walls aren't particles and can't be removed like one. */
+    Wall(RigidBodyHandle),
+    /* A wall collider has gone missing from the collider set. */
+    MissingWall,
+}
+
+impl fmt::Display for SandboxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SandboxError::Io(err) => write!(f, "{err}"),
+            SandboxError::InvalidScene => write!(f, "not a sandbox_xd scene"),
+            SandboxError::StaleHandle(handle) => write!(f, "no body with handle {handle:?}"),
+            SandboxError::Wall(handle) => write!(f, "body {handle:?} is a wall"),
+            SandboxError::MissingWall => write!(f, "a wall collider is missing"),
+        }
+    }
+}
+
+impl Error for SandboxError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SandboxError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SandboxError {
+    fn from(err: io::Error) -> Self {
+        SandboxError::Io(err)
+    }
+}
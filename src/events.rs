@@ -0,0 +1,128 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use parking_lot::Mutex;
+use rapier2d::prelude::*;
+
+/* Oldest events are dropped once this many are waiting to be drained. */
+const MAX_PENDING_EVENTS: usize = 4096;
+
+/* An impact between two colliders, with `normal` pointing away from `a`. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Contact {
+    pub a: ColliderHandle,
+    pub b: ColliderHandle,
+    pub point: Point<Real>,
+    pub normal: Vector<Real>,
+    pub impulse: Real,
+}
+
+/* A body entering or leaving a portal sensor. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crossing {
+    pub portal: ColliderHandle,
+    pub body: RigidBodyHandle,
+    pub entered: bool,
+}
+
+#[derive(Default)]
+pub struct EventCollector {
+    collisions: Mutex<VecDeque<CollisionEvent>>,
+    /* Every distinct body that has entered each registered sensor. */
+    sensors: Mutex<HashMap<ColliderHandle, HashSet<RigidBodyHandle>>>,
+    portals: Mutex<HashSet<ColliderHandle>>,
+    crossings: Mutex<Vec<Crossing>>,
+}
+
+impl EventCollector {
+    pub fn drain(&self) -> Vec<CollisionEvent> {
+        self.collisions.lock().drain(..).collect()
+    }
+
+    pub fn add_sensor(&self, handle: ColliderHandle) {
+        self.sensors.lock().entry(handle).or_default();
+    }
+
+    pub fn sensor_count(&self, handle: ColliderHandle) -> Option<usize> {
+        self.sensors.lock().get(&handle).map(HashSet::len)
+    }
+
+    pub fn sensors(&self) -> Vec<(ColliderHandle, usize)> {
+        self.sensors
+            .lock()
+            .iter()
+            .map(|(handle, entered)| (*handle, entered.len()))
+            .collect()
+    }
+
+    pub fn clear_sensors(&self) {
+        self.sensors.lock().clear();
+    }
+
+    pub fn add_portal(&self, handle: ColliderHandle) {
+        self.portals.lock().insert(handle);
+    }
+
+    pub fn drain_crossings(&self) -> Vec<Crossing> {
+        std::mem::take(&mut *self.crossings.lock())
+    }
+
+    pub fn clear_portals(&self) {
+        self.portals.lock().clear();
+        self.crossings.lock().clear();
+    }
+}
+
+impl EventHandler for EventCollector {
+    fn handle_collision_event(
+        &self,
+        _bodies: &RigidBodySet,
+        colliders: &ColliderSet,
+        event: CollisionEvent,
+        _contact_pair: Option<&ContactPair>,
+    ) {
+        if let CollisionEvent::Started(a, b, flags) = event {
+            if flags.contains(CollisionEventFlags::SENSOR) {
+                let mut sensors = self.sensors.lock();
+                for (sensor, other) in [(a, b), (b, a)] {
+                    let body = colliders.get(other).and_then(|collider| collider.parent());
+                    if let (Some(entered), Some(body)) = (sensors.get_mut(&sensor), body) {
+                        entered.insert(body);
+                    }
+                }
+            }
+        }
+
+        if event.sensor() {
+            let portals = self.portals.lock();
+            let (a, b) = (event.collider1(), event.collider2());
+            for (portal, other) in [(a, b), (b, a)] {
+                if !portals.contains(&portal) {
+                    continue;
+                }
+                if let Some(body) = colliders.get(other).and_then(|collider| collider.parent()) {
+                    self.crossings.lock().push(Crossing {
+                        portal,
+                        body,
+                        entered: event.started(),
+                    });
+                }
+            }
+        }
+
+        let mut collisions = self.collisions.lock();
+        if collisions.len() >= MAX_PENDING_EVENTS {
+            collisions.pop_front();
+        }
+        collisions.push_back(event);
+    }
+
+    fn handle_contact_force_event(
+        &self,
+        _dt: Real,
+        _bodies: &RigidBodySet,
+        _colliders: &ColliderSet,
+        _contact_pair: &ContactPair,
+        _total_force_magnitude: Real,
+    ) {
+    }
+}
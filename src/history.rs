@@ -0,0 +1,43 @@
+use std::{collections::VecDeque, time::Duration};
+
+use parking_lot::Mutex;
+
+/* About five seconds at the default step rate. */
+pub const HISTORY_LEN: usize = 300;
+
+/* Whole-scene metrics for one step. */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Sample {
+    pub tick: u64,
+    pub particles: usize,
+    pub step_time: Duration,
+    pub kinetic_energy: f32,
+}
+
+/* The last `HISTORY_LEN` steps' samples, oldest first; older ones are overwritten. */
+#[derive(Default)]
+pub struct History {
+    samples: Mutex<VecDeque<Sample>>,
+}
+
+impl History {
+    pub fn push(&self, sample: Sample) {
+        let mut samples = self.samples.lock();
+        if samples.len() >= HISTORY_LEN {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    pub fn samples(&self) -> Vec<Sample> {
+        self.samples.lock().iter().copied().collect()
+    }
+
+    pub fn latest(&self) -> Option<Sample> {
+        self.samples.lock().back().copied()
+    }
+
+    pub fn clear(&self) {
+        self.samples.lock().clear();
+    }
+}
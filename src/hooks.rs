@@ -0,0 +1,74 @@
+use std::collections::{HashMap, HashSet};
+
+use parking_lot::Mutex;
+use rapier2d::prelude::*;
+
+/* Per-collider contact tweaks applied by the solver; colliders opt in through their active hooks. */
+#[derive(Default)]
+pub struct Hooks {
+    conveyors: Mutex<HashMap<ColliderHandle, Real>>,
+    /* Platforms that only hold up what lands on them from above. */
+    oneway: Mutex<HashSet<ColliderHandle>>,
+}
+
+impl Hooks {
+    pub fn add_conveyor(&self, handle: ColliderHandle, surface_vel: Real) {
+        self.conveyors.lock().insert(handle, surface_vel);
+    }
+
+    pub fn conveyors(&self) -> Vec<(ColliderHandle, Real)> {
+        self.conveyors
+            .lock()
+            .iter()
+            .map(|(handle, speed)| (*handle, *speed))
+            .collect()
+    }
+
+    pub fn add_oneway(&self, handle: ColliderHandle) {
+        self.oneway.lock().insert(handle);
+    }
+
+    pub fn clear(&self) {
+        self.conveyors.lock().clear();
+        self.oneway.lock().clear();
+    }
+
+    fn apply_oneway(&self, context: &mut ContactModificationContext) {
+        let oneway = self.oneway.lock();
+        /* The allowed normal is in collider1's frame, pointing out of it toward collider2. y grows downward. */
+        let allowed = if oneway.contains(&context.collider1) {
+            context.colliders[context.collider1].rotation().inverse() * -Vector::y()
+        } else if oneway.contains(&context.collider2) {
+            context.colliders[context.collider1].rotation().inverse() * Vector::y()
+        } else {
+            return;
+        };
+        drop(oneway);
+        context.update_as_oneway_platform(&allowed, 0.3);
+    }
+
+    fn apply_conveyors(&self, context: &mut ContactModificationContext) {
+        let conveyors = self.conveyors.lock();
+        /* The tangent velocity is how fast collider1's surface slides relative to collider2. */
+        let (conveyor, speed) = if let Some(speed) = conveyors.get(&context.collider1) {
+            (context.collider1, *speed)
+        } else if let Some(speed) = conveyors.get(&context.collider2) {
+            (context.collider2, -*speed)
+        } else {
+            return;
+        };
+
+        /* The belt runs along its own x axis. */
+        let tangent = context.colliders[conveyor].rotation() * Vector::x();
+        for solver_contact in context.solver_contacts.iter_mut() {
+            solver_contact.tangent_velocity = tangent * speed;
+        }
+    }
+}
+
+impl PhysicsHooks for Hooks {
+    fn modify_solver_contacts(&self, context: &mut ContactModificationContext) {
+        self.apply_oneway(context);
+        self.apply_conveyors(context);
+    }
+}
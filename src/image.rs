@@ -0,0 +1,30 @@
+/* A decoded picture, row-major from the top-left, one RGBA pixel per entry. */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RgbaImage {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 4]>,
+}
+
+impl RgbaImage {
+    /* `None` unless there is exactly one pixel per cell of a `width` by `height` grid. */
+    pub fn new(width: usize, height: usize, pixels: Vec<[u8; 4]>) -> Option<Self> {
+        (width.checked_mul(height) == Some(pixels.len())).then_some(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Option<[u8; 4]> {
+        (x < self.width && y < self.height).then(|| self.pixels[y * self.width + x])
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    cell::Cell,
+    ops::{Deref, DerefMut},
+};
+
+use parking_lot::{Mutex, MutexGuard};
+
+/// The order the physics sets must be locked in, matching `State::step`.
+///
+/// A thread may only take a lock ranked after every lock it already holds,
+/// so any two methods that need several sets take them in the same order and
+/// cannot deadlock each other. Skipping ranks is fine. Debug builds assert
+/// this on every acquisition, which also catches re-locking a set the thread
+/// already holds.
+///
+/// Small bookkeeping mutexes on `State` (bounds, settings, platforms, the
+/// wall handles) are not ranked; they may be taken while holding any of
+/// these, but never held while acquiring one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rank {
+    PhysicsPipeline,
+    IslandManager,
+    BroadPhase,
+    NarrowPhase,
+    RigidBodySet,
+    ColliderSet,
+    ImpulseJointSet,
+    MultibodyJointSet,
+    CcdSolver,
+    QueryPipeline,
+}
+
+thread_local! {
+    /* One bit per rank currently held by this thread. */
+    static HELD: Cell<u32> = const { Cell::new(0) };
+}
+
+pub struct OrderedMutex<T> {
+    rank: Rank,
+    inner: Mutex<T>,
+}
+
+impl<T> OrderedMutex<T> {
+    pub fn new(rank: Rank, value: T) -> Self {
+        OrderedMutex {
+            rank,
+            inner: Mutex::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> OrderedGuard<'_, T> {
+        let bit = 1 << self.rank as u32;
+        if cfg!(debug_assertions) {
+            HELD.with(|held| {
+                /* Any held bit at or above this rank means the order is broken. */
+                debug_assert!(
+                    held.get() & !(bit - 1) == 0,
+                    "{:?} locked out of order (held ranks {:#b})",
+                    self.rank,
+                    held.get()
+                );
+                held.set(held.get() | bit);
+            });
+        }
+        OrderedGuard {
+            bit,
+            guard: self.inner.lock(),
+        }
+    }
+}
+
+pub struct OrderedGuard<'a, T> {
+    bit: u32,
+    guard: MutexGuard<'a, T>,
+}
+
+impl<T> Deref for OrderedGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for OrderedGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for OrderedGuard<'_, T> {
+    fn drop(&mut self) {
+        if cfg!(debug_assertions) {
+            HELD.with(|held| held.set(held.get() & !self.bit));
+        }
+    }
+}
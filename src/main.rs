@@ -1,71 +1,1349 @@
-use std::{sync::Arc, time::SystemTime};
+use std::{sync::Arc, time::Duration};
 
+use command::Command;
+use console::Console;
+use history::{Sample, HISTORY_LEN};
+use image::RgbaImage;
 use rapier2d::prelude::*;
 use raylib::prelude::*;
-use state::State;
+use state::{
+    spawn_index, triangle_points, Edge, GravityFrame, ParticleInfo, ParticleShape,
+    ParticleSnapshot, SpawnShape, State, WallMoveMode, PORTAL_RADIUS,
+};
 
+pub mod command;
+pub mod console;
+pub mod error;
+pub mod events;
+pub mod history;
+pub mod hooks;
+pub mod image;
+pub mod lock;
+pub mod rng;
+pub mod scene;
 pub mod state;
 
+#[derive(Clone, Copy, PartialEq)]
+enum Tool {
+    Particle,
+    Sand,
+    Spin,
+}
+
+#[cfg(feature = "tokio")]
 #[tokio::main]
 async fn main() {
     let state = Arc::new(State::new());
+    state.set_step_budget(Some(Duration::from_millis(8)));
 
+    let single_threaded = single_threaded();
     let s1 = state.clone();
-    let s2 = state.clone();
 
-    tokio::spawn(async move {
-        let mut time = SystemTime::now();
-        loop {
-            //if time.elapsed().unwrap().as_secs_f64() >= 1.0 / 480.0 {
-            s1.step();
-            time = SystemTime::now();
-            //}
+    if !single_threaded {
+        /* Paced like `State::run_physics_thread`, sleeping out the rest of each step. */
+        tokio::spawn(async move {
+            let timestep = Duration::from_secs_f32(1.0 / s1.step_hz());
+            let mut next = tokio::time::Instant::now();
+            loop {
+                s1.step();
+                next += timestep;
+                if s1.is_over_budget() {
+                    /* Halve the step rate until the load drops, leaving the renderer room to keep up. */
+                    next += s1.step_time();
+                }
+
+                let now = tokio::time::Instant::now();
+                if next > now {
+                    tokio::time::sleep_until(next).await;
+                } else {
+                    /* Running behind: carry on from now rather than bursting to catch up. */
+                    next = now;
+                }
+            }
+        });
+    }
+
+    render(state, single_threaded);
+}
+
+#[cfg(not(feature = "tokio"))]
+fn main() {
+    let state = Arc::new(State::new());
+    state.set_step_budget(Some(Duration::from_millis(8)));
+
+    let single_threaded = single_threaded();
+    let _physics = (!single_threaded).then(|| state.clone().run_physics_thread());
+
+    render(state, single_threaded);
+}
+
+/* `--single-threaded` steps the physics from the render loop, a few steps per frame, instead of on its own thread. */
+fn single_threaded() -> bool {
+    std::env::args().any(|arg| arg == "--single-threaded")
+}
+
+/* The most steps one frame runs when single-threaded; a slow frame drops the rest rather than snowballing. */
+const MAX_STEPS_PER_FRAME: usize = 8;
+
+/* How far a run without a display gets before exiting. */
+const HEADLESS_STEPS: u64 = 600;
+
+/* raylib panics when there is no display to open a window on; that panic is kept quiet, others still report. */
+fn open_window(builder: &RaylibBuilder) -> Option<(RaylibHandle, RaylibThread)> {
+    let previous = Arc::new(std::panic::take_hook());
+    let this_thread = std::thread::current().id();
+    let hook = previous.clone();
+    std::panic::set_hook(Box::new(move |info| {
+        if std::thread::current().id() != this_thread {
+            hook(info);
         }
-    });
+    }));
+    let window = std::panic::catch_unwind(|| builder.build()).ok();
+    std::panic::set_hook(Box::new(move |info| previous(info)));
+    window
+}
+
+/* Step to `HEADLESS_STEPS`, or wait for the physics thread to get there, then report what it did. */
+fn run_headless(s2: &State, single_threaded: bool) {
+    while s2.tick() < HEADLESS_STEPS {
+        if single_threaded {
+            s2.step();
+        } else {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+    let stats = s2.diagnostics();
+    println!(
+        "tick {}: {} bodies ({} awake), {} contacts",
+        s2.tick(),
+        stats.bodies,
+        stats.awake,
+        stats.contacts
+    );
+}
 
-    let (mut rl, thread) = raylib::init()
+fn render(s2: Arc<State>, single_threaded: bool) {
+    let mut builder = raylib::init();
+    builder
         .width(320)
         .height(240)
         .title("sandbox_xd")
-        .resizable()
-        .build();
+        .resizable();
+    if s2.target_fps().is_none() {
+        builder.vsync();
+    }
+    let Some((mut rl, thread)) = open_window(&builder) else {
+        eprintln!("no window could be opened; running {HEADLESS_STEPS} steps headless instead");
+        run_headless(&s2, single_threaded);
+        return;
+    };
+    if let Some(fps) = s2.target_fps() {
+        rl.set_target_fps(fps);
+    }
 
-    let mut last_x = 0.0;
-    let mut last_y = 0.0;
+    /* Start from the real window position so spawns line up before the first resize. */
+    let wpos = rl.get_window_position();
+    let mut window = (wpos.x, wpos.y);
     let mut last_width = 0;
     let mut last_height = 0;
-    let wpos = rl.get_window_position();
+    let mut ground_halfspace = false;
+    let mut show_elasticity = false;
+    let mut show_stress = false;
+    let mut show_aabbs = false;
+    let mut show_contacts = false;
+    let mut show_density = false;
+    let mut show_stats = false;
+    let mut bouncy_walls = false;
+    let mut show_trails = false;
+    let mut render_walls = false;
+    let mut show_ids = false;
+    let mut show_age = false;
+    let mut cohesive = false;
+    let mut air_drag = false;
+    /* What was last drawn, redrawn as-is while rendering is paused. */
+    let mut particles = Vec::new();
+    let mut emitters = 0;
+    let mut portal_start = None;
+    #[cfg(feature = "parallel")]
+    let mut parallel = false;
+    let mut tool = Tool::Particle;
+    let mut pour_pending = 0.0;
+    let mut steps_pending = 0.0;
+    let mut console = Console::default();
+    /* Drawn to the screen directly unless Shift+F10 routes the scene through this. */
+    let mut target: Option<RenderTexture2D> = None;
 
     while !rl.window_should_close() {
         let wpos = rl.get_window_position();
         let size = (rl.get_screen_width(), rl.get_screen_height());
-        if wpos.x != last_x || wpos.y != last_y || size.0 != last_width || size.1 != last_height {
-            s2.resize(wpos.x, wpos.y, size.0 as f32, size.1 as f32);
-            last_x = wpos.x;
-            last_y = wpos.y;
+        if (wpos.x, wpos.y) != window || size.0 != last_width || size.1 != last_height {
+            if let Err(err) = s2.resize(wpos.x, wpos.y, size.0 as f32, size.1 as f32) {
+                eprintln!("couldn't move the walls: {err}");
+            }
+            window = (wpos.x, wpos.y);
             last_width = size.0;
             last_height = size.1;
+            if target.is_some() {
+                target = load_target(&mut rl, &thread, size);
+            }
+        }
+        /* The world point under the window's top-left: the window itself, or the arena centered in it. */
+        let (view_x, view_y) = match s2.arena() {
+            Some((width, height)) => (
+                (width - size.0 as f32) / 2.0,
+                (height - size.1 as f32) / 2.0,
+            ),
+            None => window,
+        };
+        console.update(&mut rl, &s2, (view_x, view_y));
+
+        /* Typing into the open console shouldn't also fire the key bindings. */
+        if !console.is_open() {
+            let wheel = rl.get_mouse_wheel_move();
+            if wheel != 0.0 {
+                if tool == Tool::Spin {
+                    let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                    if let Some(handle) = s2.particle_at(x, y) {
+                        s2.set_angular_velocity(handle, wheel * 10.0);
+                    }
+                } else {
+                    s2.set_spawn_size(s2.spawn_settings().size + wheel * 0.5);
+                }
+            }
+
+            /* Ctrl+left-drag moves a particle instead of spawning. */
+            let ctrl = rl.is_key_down(KeyboardKey::KEY_LEFT_CONTROL)
+                || rl.is_key_down(KeyboardKey::KEY_RIGHT_CONTROL);
+            if ctrl && rl.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_LEFT) {
+                let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                s2.begin_drag(x, y);
+            }
+            if rl.is_mouse_button_released(MouseButton::MOUSE_BUTTON_LEFT) {
+                s2.end_drag();
+            }
+
+            if rl.is_mouse_button_down(MouseButton::MOUSE_BUTTON_LEFT) {
+                let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+
+                match tool {
+                    _ if s2.is_dragging() => s2.drag_to(x, y),
+                    /* A Ctrl-click that missed shouldn't spawn either. */
+                    _ if ctrl => {}
+                    Tool::Particle | Tool::Spin => {
+                        s2.enqueue_command(Command::Spawn { x, y });
+                    }
+                    Tool::Sand => {
+                        s2.insert_sand(x, y, 8);
+                    }
+                }
+            }
+
+            /* Middle-click twice to link a pair of portals. */
+            if rl.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_MIDDLE) {
+                let point = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                match portal_start.take() {
+                    Some(start) => {
+                        s2.add_portal_pair(start, point);
+                    }
+                    None => portal_start = Some(point),
+                }
+            }
+
+            /* Alt+right-click shatters a particle; Shift+right-drag erases everything under a brush. */
+            let alt = rl.is_key_down(KeyboardKey::KEY_LEFT_ALT)
+                || rl.is_key_down(KeyboardKey::KEY_RIGHT_ALT);
+            let shift = rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT);
+            if alt && rl.is_mouse_button_pressed(MouseButton::MOUSE_BUTTON_RIGHT) {
+                let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                if let Some(handle) = s2.particle_at(x, y) {
+                    s2.fragment(handle, 4);
+                }
+            } else if shift && rl.is_mouse_button_down(MouseButton::MOUSE_BUTTON_RIGHT) {
+                let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                s2.erase_brush(x, y, 16.0);
+            } else if !alt && rl.is_mouse_button_down(MouseButton::MOUSE_BUTTON_RIGHT) {
+                let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                s2.enqueue_command(Command::Remove { x, y });
+            }
+
+            /* Pour from the cursor while P is held, carrying fractional particles across frames. */
+            if rl.is_key_down(KeyboardKey::KEY_P) {
+                pour_pending += s2.pour_rate() * rl.get_frame_time();
+                let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                while pour_pending >= 1.0 {
+                    s2.insert_particle_with_velocity(x, y, 0.0, 40.0);
+                    pour_pending -= 1.0;
+                }
+            } else {
+                pour_pending = 0.0;
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_TAB) {
+                s2.set_spawn_shape(s2.spawn_settings().shape.next());
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_O) {
+                tool = match tool {
+                    Tool::Spin => Tool::Particle,
+                    _ => Tool::Spin,
+                };
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_S) {
+                tool = match tool {
+                    Tool::Sand => Tool::Particle,
+                    _ => Tool::Sand,
+                };
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_T) {
+                let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                let track: Vec<(f32, f32)> = (0..=16)
+                    .map(|i| {
+                        let t = i as f32 / 16.0;
+                        (x + t * 120.0, y + (t * std::f32::consts::PI).sin() * 30.0)
+                    })
+                    .collect();
+                s2.insert_polyline(&track);
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_M) {
+                let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                s2.add_moving_platform(x, y, 60.0, 6.0, 40.0, 4.0);
+            }
+
+            /* Hold W to gust rightward, or with shift to gust leftward; Ctrl+W wraps the edges around instead of walling them. */
+            if ctrl && rl.is_key_pressed(KeyboardKey::KEY_W) {
+                if let Err(err) = s2.set_wrap(!s2.is_wrapping()) {
+                    eprintln!("couldn't change the edges: {err}");
+                }
+            } else if !ctrl && rl.is_key_down(KeyboardKey::KEY_W) {
+                let shift = rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT)
+                    || rl.is_key_down(KeyboardKey::KEY_RIGHT_SHIFT);
+                s2.set_wind(vector![if shift { -20.0 } else { 20.0 }, 0.0]);
+            } else if rl.is_key_released(KeyboardKey::KEY_W) {
+                s2.set_wind(vector![0.0, 0.0]);
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_A) {
+                /* A lumpy asteroid: the hull of a few points scattered around the cursor. */
+                let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                let radius = s2.spawn_settings().size * 2.0;
+                let points: Vec<_> = (0..8)
+                    .map(|_| {
+                        let angle = s2.random_range(0.0, std::f32::consts::TAU);
+                        let distance = s2.random_range(radius * 0.5, radius);
+                        (x + angle.cos() * distance, y + angle.sin() * distance)
+                    })
+                    .collect();
+                s2.insert_convex(&points);
+            }
+
+            /* X reshapes the particle under the cursor into the selected spawn shape. */
+            if rl.is_key_pressed(KeyboardKey::KEY_X) {
+                let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                if let Some(handle) = s2.particle_at(x, y) {
+                    s2.set_shape(handle, s2.spawn_settings().shape);
+                }
+            }
+
+            /* 1 and 2 drop "metal" and "glass" into two groups that pass through each other; with Shift they pull that tag in. */
+            for (key, group, tag) in [
+                (KeyboardKey::KEY_ONE, 1, "metal"),
+                (KeyboardKey::KEY_TWO, 2, "glass"),
+            ] {
+                if rl.is_key_down(key) {
+                    let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                    if rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) {
+                        s2.attract_tagged(x, y, 80.0, 4.0, tag);
+                    } else if let Some(handle) = s2.insert_in_group(x, y, group) {
+                        s2.set_tag(handle, tag);
+                    }
+                }
+            }
+
+            /* I adds an emitter on the next edge round; Shift+I removes them all. */
+            if rl.is_key_pressed(KeyboardKey::KEY_I) {
+                if rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) {
+                    s2.clear_emitters();
+                    emitters = 0;
+                } else {
+                    let edge = [Edge::Top, Edge::Left, Edge::Right, Edge::Bottom][emitters % 4];
+                    s2.add_emitter(edge, 10.0, 60.0);
+                    emitters += 1;
+                }
+            }
+
+            /* V marks a goal zone under the cursor that counts the particles entering it;
+             * Shift+V places a zone where gravity is reversed instead. */
+            if rl.is_key_pressed(KeyboardKey::KEY_V) {
+                let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                if rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) {
+                    s2.add_gravity_zone(x, y, 80.0, 80.0, -s2.gravity());
+                } else {
+                    s2.add_sensor(x, y, 60.0, 30.0);
+                }
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_F) {
+                s2.flip_horizontal();
+            }
+
+            /* 3, 4 and 5 paint red, green and blue particles. */
+            for (key, rgb) in [
+                (KeyboardKey::KEY_THREE, (230, 41, 55)),
+                (KeyboardKey::KEY_FOUR, (0, 228, 48)),
+                (KeyboardKey::KEY_FIVE, (0, 121, 241)),
+            ] {
+                if rl.is_key_down(key) {
+                    let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                    s2.insert_colored(x, y, rgb);
+                }
+            }
+
+            /* [ and ] shrink and grow everything in the box. */
+            if rl.is_key_pressed(KeyboardKey::KEY_LEFT_BRACKET) {
+                s2.scale_scene(0.8);
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_RIGHT_BRACKET) {
+                s2.scale_scene(1.25);
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_U) {
+                let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                s2.add_bucket(x, y, 60.0, 40.0, 4.0);
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_J) {
+                let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                s2.spawn_chain(x, y, 10, 8.0);
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_C) {
+                let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                let shift = rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT)
+                    || rl.is_key_down(KeyboardKey::KEY_RIGHT_SHIFT);
+                s2.insert_conveyor(x, y, 120.0, 8.0, if shift { -20.0 } else { 20.0 });
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_H) {
+                let heights: Vec<f32> = (0..=32)
+                    .map(|i| {
+                        let t = i as f32 / 32.0 * std::f32::consts::TAU;
+                        20.0 + 15.0 * (t * 2.0).sin() + 5.0 * (t * 5.0).cos()
+                    })
+                    .collect();
+                s2.insert_heightfield(
+                    view_x + size.0 as f32 / 2.0,
+                    view_y + size.1 as f32,
+                    &heights,
+                    size.0 as f32,
+                );
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_G) {
+                ground_halfspace = !ground_halfspace;
+                if let Err(err) = s2.set_ground_halfspace(ground_halfspace) {
+                    eprintln!("couldn't swap the ground: {err}");
+                }
+            }
+
+            /* E tints particles by bounciness; Shift+E by how much load they carry.
+             * Ctrl+E makes new particles a step bouncier, wrapping back round to dead. */
+            if rl.is_key_pressed(KeyboardKey::KEY_E) {
+                if ctrl {
+                    let restitution = s2.spawn_settings().restitution + 0.25;
+                    s2.set_spawn_restitution(if restitution > 1.0 { 0.0 } else { restitution });
+                } else if rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) {
+                    show_stress = !show_stress;
+                } else {
+                    show_elasticity = !show_elasticity;
+                }
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_N) {
+                s2.set_grid_snap(match s2.grid_snap() {
+                    Some(_) => None,
+                    None => Some(8.0),
+                });
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_D) {
+                show_density = !show_density;
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_F1) {
+                show_stats = !show_stats;
+            }
+
+            /* K toggles bouncy walls; Shift+K shakes the box and Ctrl+K collapses it inward instead. */
+            if rl.is_key_pressed(KeyboardKey::KEY_K) {
+                if ctrl {
+                    s2.implode(200.0);
+                } else if rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) {
+                    s2.shake(40.0);
+                } else {
+                    bouncy_walls = !bouncy_walls;
+                    let (friction, restitution) =
+                        if bouncy_walls { (0.0, 1.0) } else { (0.5, 0.0) };
+                    if let Err(err) = s2.set_wall_material(friction, restitution) {
+                        eprintln!("couldn't change the walls: {err}");
+                    }
+                }
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_Y) {
+                show_trails = !show_trails;
+                s2.set_trail_length(if show_trails { 16 } else { 0 });
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_F2) {
+                render_walls = !render_walls;
+            }
+
+            #[cfg(feature = "parallel")]
+            if rl.is_key_pressed(KeyboardKey::KEY_F4) {
+                parallel = !parallel;
+                s2.set_parallel(parallel);
+            }
+
+            /* Q makes the particles stick together like a thick liquid. */
+            if rl.is_key_pressed(KeyboardKey::KEY_Q) {
+                cohesive = !cohesive;
+                s2.set_cohesion(if cohesive { 200.0 } else { 0.0 }, 16.0);
+            }
+
+            /* 9 switches between particles and gravity staying put and following the window as it moves or reshapes. */
+            if rl.is_key_pressed(KeyboardKey::KEY_NINE) {
+                s2.set_gravity_frame(match s2.gravity_frame() {
+                    GravityFrame::World => GravityFrame::Window,
+                    GravityFrame::Window => GravityFrame::World,
+                });
+            }
+
+            /* 8 lays a one-way platform at the cursor. */
+            if rl.is_key_pressed(KeyboardKey::KEY_EIGHT) {
+                let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                s2.insert_oneway_platform(x, y, 80.0, 4.0);
+            }
+
+            /* 7 pulls the top particle off the stack under the cursor. */
+            if rl.is_key_pressed(KeyboardKey::KEY_SEVEN) {
+                let (x, _) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                if let Some(handle) = s2.topmost_at_column(x, 4.0) {
+                    if let Err(err) = s2.remove(handle) {
+                        eprintln!("couldn't remove the top particle: {err}");
+                    }
+                }
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_SIX) {
+                air_drag = !air_drag;
+                s2.set_quadratic_drag(if air_drag { 0.1 } else { 0.0 });
+            }
+
+            /* F5 welds everything near the cursor into one fixed piece. */
+            if rl.is_key_pressed(KeyboardKey::KEY_F5) {
+                let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                s2.weld_selection(&s2.particles_in_radius(x, y, 40.0));
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_F7) {
+                if let Err(err) = s2.export_csv("sandbox_xd.csv") {
+                    eprintln!("couldn't export sandbox_xd.csv: {err}");
+                }
+            }
+
+            /* F8 saves the particles to sandbox_xd.scene and F9 adds them back;
+             * Ctrl+F9 builds sandbox_xd.png out of particles at the cursor instead. */
+            if rl.is_key_pressed(KeyboardKey::KEY_F8) {
+                if let Err(err) = s2.save("sandbox_xd.scene") {
+                    eprintln!("couldn't save sandbox_xd.scene: {err}");
+                }
+            }
+            if rl.is_key_pressed(KeyboardKey::KEY_F9) {
+                if ctrl {
+                    let (x, y) = screen_to_world(rl.get_mouse_position(), (view_x, view_y));
+                    match load_picture("sandbox_xd.png") {
+                        Ok(picture) => {
+                            s2.spawn_from_image(&picture, 2.0, (x, y));
+                        }
+                        Err(err) => eprintln!("couldn't load sandbox_xd.png: {err}"),
+                    }
+                } else if let Err(err) = s2.load("sandbox_xd.scene") {
+                    eprintln!("couldn't load sandbox_xd.scene: {err}");
+                }
+            }
+
+            /* F10 pins the walls to a 640x480 arena, independent of the window;
+             * Shift+F10 switches to drawing the scene off-screen and blitting it,
+             * and Ctrl+F10 between walls that jump and walls that glide to a new size. */
+            if rl.is_key_pressed(KeyboardKey::KEY_F10) {
+                if ctrl {
+                    let mode = match s2.wall_move_mode() {
+                        WallMoveMode::Teleport => WallMoveMode::Kinematic,
+                        WallMoveMode::Kinematic => WallMoveMode::Teleport,
+                    };
+                    if let Err(err) = s2.set_wall_move_mode(mode) {
+                        eprintln!("couldn't change how the walls move: {err}");
+                    }
+                } else if rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) {
+                    target = match target {
+                        Some(_) => None,
+                        None => load_target(&mut rl, &thread, size),
+                    };
+                } else if s2.arena().is_some() {
+                    s2.clear_arena();
+                    last_width = 0;
+                } else if let Err(err) = s2.set_arena(640.0, 480.0) {
+                    eprintln!("couldn't set up the arena: {err}");
+                }
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_F6) {
+                show_age = !show_age;
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_F3) {
+                show_ids = !show_ids;
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_B) {
+                show_aabbs = !show_aabbs;
+            }
+
+            /* F12 makes new particles spawn asleep, for building arrangements that hold still;
+             * Shift+F12 wakes everything to release them. */
+            if rl.is_key_pressed(KeyboardKey::KEY_F12) {
+                if rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) {
+                    s2.wake_all();
+                } else {
+                    s2.set_spawn_asleep(!s2.spawn_settings().asleep);
+                }
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_F11) {
+                show_contacts = !show_contacts;
+            }
+
+            if ctrl && rl.is_key_pressed(KeyboardKey::KEY_Z) {
+                s2.undo();
+            }
+
+            if rl.is_key_pressed(KeyboardKey::KEY_R) {
+                s2.reset();
+            }
+
+            /* Space pauses physics; Shift+Space freezes the picture while physics runs on. */
+            if rl.is_key_pressed(KeyboardKey::KEY_SPACE) {
+                if rl.is_key_down(KeyboardKey::KEY_LEFT_SHIFT) {
+                    s2.set_render_paused(!s2.is_render_paused());
+                } else {
+                    s2.set_physics_paused(!s2.is_physics_paused());
+                }
+            }
+        }
+
+        if single_threaded {
+            steps_pending += rl.get_frame_time() * s2.step_hz();
+            let steps = steps_pending.floor();
+            steps_pending -= steps;
+            for _ in 0..(steps as usize).min(MAX_STEPS_PER_FRAME) {
+                s2.step();
+            }
         }
-        if rl.is_mouse_button_down(MouseButton::MOUSE_BUTTON_LEFT) {
-            let pos = rl.get_mouse_position();
 
-            s2.insert_particle(pos.x + last_x, pos.y + last_y);
+        if !s2.is_render_paused() {
+            particles = s2.interpolated_snapshots(s2.step_alpha());
         }
+        let frame = Frame {
+            view: (view_x, view_y),
+            size,
+            time: rl.get_time() as f32,
+            mouse: rl.get_mouse_position(),
+            laser: rl.is_key_down(KeyboardKey::KEY_L),
+            tool,
+            portal_start,
+            render_walls,
+            show_elasticity,
+            show_stress,
+            show_age,
+            show_ids,
+            show_density,
+            show_aabbs,
+            show_contacts,
+        };
 
         let mut d = rl.begin_drawing(&thread);
-        d.clear_background(Color::WHITE);
-
-        s2.for_each_cube(|x, y, width, height, rand| {
-            if rand == 0 {
-                return;
-            }
-            d.draw_rectangle(
-                x as i32 - last_x as i32,
-                y as i32 - last_y as i32,
-                width as i32,
-                height as i32,
-                Color::new((rand ^ 0xFF0000) as u8, 0, 0, 255),
+        match target.as_mut() {
+            Some(target) => {
+                draw_scene(
+                    &mut d.begin_texture_mode(&thread, target),
+                    &s2,
+                    &frame,
+                    &particles,
+                );
+                /* Render textures are stored bottom-up, so flip the source rectangle to blit upright. */
+                let (width, height) = (target.texture.width as f32, target.texture.height as f32);
+                d.draw_texture_rec(
+                    &*target,
+                    Rectangle::new(0.0, 0.0, width, -height),
+                    Vector2::new(0.0, 0.0),
+                    Color::WHITE,
+                );
+            }
+            None => draw_scene(&mut d, &s2, &frame, &particles),
+        }
+
+        d.draw_text(
+            &format!(
+                "tick {}  step {:.2}ms  contacts {}{}",
+                s2.tick(),
+                s2.step_time().as_secs_f64() * 1000.0,
+                s2.contact_count(),
+                match (s2.is_physics_paused(), s2.is_render_paused()) {
+                    (true, true) => "  paused",
+                    (true, false) => "  physics paused",
+                    (false, true) => "  render paused",
+                    (false, false) => "",
+                }
+            ),
+            4,
+            4,
+            10,
+            Color::BLACK,
+        );
+
+        /* A compass in the top-right corner pointing the way gravity pulls. */
+        let compass = Vector2::new(size.0 as f32 - 16.0, 16.0);
+        let (gx, gy) = s2.gravity_direction();
+        let tip = Vector2::new(compass.x + gx * 10.0, compass.y + gy * 10.0);
+        d.draw_circle_lines(compass.x as i32, compass.y as i32, 12.0, Color::LIGHTGRAY);
+        d.draw_line_v(compass, tip, Color::BLACK);
+        d.draw_circle_v(tip, 2.0, Color::BLACK);
+
+        if show_stats {
+            draw_history(
+                &mut d,
+                &s2.history().samples(),
+                Rectangle::new(4.0, 30.0, 150.0, 40.0),
+            );
+
+            let mut shapes: Vec<_> = s2.shape_histogram().into_iter().collect();
+            shapes.sort();
+            let shapes: Vec<_> = shapes
+                .iter()
+                .map(|(label, count)| format!("{label} {count}"))
+                .collect();
+            d.draw_text(&shapes.join("  "), 4, 74, 10, Color::BLACK);
+
+            if let Some((x, y)) = s2.center_of_mass() {
+                let (x, y) = (x - view_x, y - view_y);
+                d.draw_line_v(
+                    Vector2::new(x - 6.0, y),
+                    Vector2::new(x + 6.0, y),
+                    Color::RED,
+                );
+                d.draw_line_v(
+                    Vector2::new(x, y - 6.0),
+                    Vector2::new(x, y + 6.0),
+                    Color::RED,
+                );
+            }
+
+            let stats = s2.diagnostics();
+            let (x, y) = screen_to_world(d.get_mouse_position(), (view_x, view_y));
+
+            /* Mark what the particle under the cursor is holding up. */
+            if let Some(base) = s2.particle_at(x, y) {
+                let resting = s2.bodies_resting_on(base);
+                for particle in particles
+                    .iter()
+                    .filter(|p| p.handle.is_some_and(|h| resting.contains(&h)))
+                {
+                    d.draw_circle_v(
+                        Vector2::new(particle.x - view_x, particle.y - view_y),
+                        2.0,
+                        Color::ORANGE,
+                    );
+                }
+            }
+            let near = s2.particles_in_radius(x, y, 32.0).len();
+            let bounds = s2.bounds();
+            let fill = s2.total_particle_area() / (bounds.width * bounds.height).max(1.0);
+            d.draw_text(
+                &format!(
+                    "bodies {} ({} awake)  colliders {}  joints {}  contacts {}  ~{} KiB  impact {:.1}  near cursor {}  fill {:.1}%",
+                    stats.bodies,
+                    stats.awake,
+                    stats.colliders,
+                    stats.joints,
+                    stats.contacts,
+                    stats.memory_bytes / 1024,
+                    s2.strongest_impact().unwrap_or(0.0),
+                    near,
+                    fill * 100.0
+                ),
+                4,
+                16,
+                10,
+                Color::BLACK,
+            );
+
+            let mouse = d.get_mouse_position();
+            if let Some(info) = s2.particle_at(x, y).and_then(|handle| s2.inspect(handle)) {
+                draw_inspector(&mut d, &info, mouse);
+            }
+        }
+
+        console.draw(&mut d, size.0, size.1);
+    }
+}
+
+/* Everything the scene is drawn from besides the state, gathered before drawing starts. */
+struct Frame {
+    view: (f32, f32),
+    size: (i32, i32),
+    time: f32,
+    mouse: Vector2,
+    laser: bool,
+    tool: Tool,
+    portal_start: Option<(f32, f32)>,
+    render_walls: bool,
+    show_elasticity: bool,
+    show_stress: bool,
+    show_age: bool,
+    show_ids: bool,
+    show_density: bool,
+    show_aabbs: bool,
+    show_contacts: bool,
+}
+
+/* The world under the HUD, onto either the screen or an off-screen texture. */
+fn draw_scene(d: &mut impl RaylibDraw, s2: &State, frame: &Frame, particles: &[ParticleSnapshot]) {
+    let (view_x, view_y) = frame.view;
+    let size = frame.size;
+    d.clear_background(Color::WHITE);
+    if let Some((width, height)) = s2.arena() {
+        /* Letterbox: shade everything outside the arena. */
+        let shade = Color::new(0, 0, 0, 160);
+        let (left, top) = (-view_x, -view_y);
+        d.draw_rectangle(0, 0, size.0, top as i32, shade);
+        d.draw_rectangle(0, (top + height) as i32, size.0, size.1, shade);
+        d.draw_rectangle(0, top as i32, left as i32, height as i32, shade);
+        d.draw_rectangle(
+            (left + width) as i32,
+            top as i32,
+            size.0,
+            height as i32,
+            shade,
+        );
+    }
+
+    if let Some(grid) = s2.grid_snap() {
+        /* Grid lines sit on world multiples of the grid size, so offset by the window position. */
+        let faint = Color::new(0, 0, 0, 24);
+        let mut x = (-view_x).rem_euclid(grid);
+        while x < size.0 as f32 {
+            d.draw_line(x as i32, 0, x as i32, size.1, faint);
+            x += grid;
+        }
+        let mut y = (-view_y).rem_euclid(grid);
+        while y < size.1 as f32 {
+            d.draw_line(0, y as i32, size.0, y as i32, faint);
+            y += grid;
+        }
+    }
+
+    if frame.render_walls {
+        /* Outline the inner faces of the walls rather than the walls themselves. */
+        let bounds = s2.bounds();
+        d.draw_rectangle_lines_ex(
+            Rectangle::new(
+                bounds.x + 1.0 - view_x,
+                bounds.y + 1.0 - view_y,
+                bounds.width - 2.0,
+                bounds.height - 2.0,
+            ),
+            1.0,
+            Color::DARKGRAY,
+        );
+    }
+
+    /* Trails fade in from their oldest point and are drawn beneath the particles. */
+    s2.for_each_trail(|points, (r, g, b)| {
+        let color = Color::new(r, g, b, 255);
+        for (i, segment) in points.windows(2).enumerate() {
+            d.draw_line_v(
+                Vector2::new(segment[0].0 - view_x, segment[0].1 - view_y),
+                Vector2::new(segment[1].0 - view_x, segment[1].1 - view_y),
+                color.fade((i + 1) as f32 / points.len() as f32),
+            );
+        }
+    });
+
+    let stress: Vec<f32> = if frame.show_stress {
+        particles
+            .iter()
+            .map(|particle| {
+                particle
+                    .handle
+                    .map_or(0.0, |handle| s2.body_impulse(handle))
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let peak_stress = stress.iter().copied().fold(f32::EPSILON, f32::max);
+    for (i, particle) in particles.iter().enumerate() {
+        let color = if frame.show_stress {
+            /* Gray when unloaded, through to red for the most loaded body in view. */
+            let t = stress[i] / peak_stress;
+            let fade = |from: u8| (from as f32 * (1.0 - t)) as u8;
+            Color::new(200, fade(200), fade(200), 255)
+        } else if frame.show_elasticity {
+            /* Blue for dead blocks through to red for fully elastic ones. */
+            let t = particle.restitution.clamp(0.0, 1.0);
+            Color::new((t * 255.0) as u8, 0, ((1.0 - t) * 255.0) as u8, 255)
+        } else if frame.show_age {
+            /* Cool from the particle's own color to slate, settling there after a minute. */
+            let t = (particle.age / 60.0).min(1.0);
+            let (r, g, b) = particle.color;
+            let mix = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * t) as u8;
+            Color::new(mix(r, 70), mix(g, 90), mix(b, 140), 255)
+        } else {
+            let (r, g, b) = particle.color;
+            Color::new(r, g, b, 255)
+        };
+        draw_particle(d, particle, (view_x, view_y), color);
+        if frame.show_ids && particle.handle.is_some() {
+            d.draw_text(
+                &spawn_index(particle.user_data).to_string(),
+                (particle.x - view_x) as i32,
+                (particle.y - view_y) as i32,
+                8,
+                Color::BLACK,
+            );
+        }
+    }
+
+    let extent = (size.0 + size.1) as f32 * 2.0;
+    s2.for_each_halfspace(|(px, py), (nx, ny), _| {
+        let (px, py) = (px - view_x, py - view_y);
+        let (tx, ty) = (-ny * extent, nx * extent);
+        let (dx, dy) = (-nx * extent, -ny * extent);
+        let corners = [
+            Vector2::new(px + tx, py + ty),
+            Vector2::new(px - tx, py - ty),
+            Vector2::new(px - tx + dx, py - ty + dy),
+            Vector2::new(px + tx + dx, py + ty + dy),
+        ];
+        draw_quad(d, corners, Color::LIGHTGRAY);
+    });
+
+    s2.for_each_heightfield(|points, _| {
+        let bottom = size.1 as f32;
+        for segment in points.windows(2) {
+            let (ax, ay) = (segment[0].0 - view_x, segment[0].1 - view_y);
+            let (bx, by) = (segment[1].0 - view_x, segment[1].1 - view_y);
+            let corners = [
+                Vector2::new(ax, ay),
+                Vector2::new(bx, by),
+                Vector2::new(bx, bottom.max(by)),
+                Vector2::new(ax, bottom.max(ay)),
+            ];
+            draw_quad(d, corners, Color::BROWN);
+        }
+    });
+
+    s2.for_each_polyline(|points, _| {
+        for segment in points.windows(2) {
+            d.draw_line(
+                segment[0].0 as i32 - view_x as i32,
+                segment[0].1 as i32 - view_y as i32,
+                segment[1].0 as i32 - view_x as i32,
+                segment[1].1 as i32 - view_y as i32,
+                Color::DARKGRAY,
+            );
+        }
+    });
+
+    s2.for_each_joint(|(ax, ay), (px, py), (bx, by)| {
+        let pivot = Vector2::new(px - view_x, py - view_y);
+        d.draw_line_v(
+            Vector2::new(ax - view_x, ay - view_y),
+            pivot,
+            Color::DARKGRAY,
+        );
+        d.draw_line_v(
+            pivot,
+            Vector2::new(bx - view_x, by - view_y),
+            Color::DARKGRAY,
+        );
+        d.draw_circle_v(pivot, 1.5, Color::BLACK);
+    });
+
+    s2.for_each_portal(|(x, y), radius, (to_x, to_y)| {
+        d.draw_circle_lines(
+            (x - view_x) as i32,
+            (y - view_y) as i32,
+            radius,
+            Color::PURPLE,
+        );
+        d.draw_line_v(
+            Vector2::new(x - view_x, y - view_y),
+            Vector2::new(to_x - view_x, to_y - view_y),
+            Color::PURPLE.fade(0.2),
+        );
+    });
+    if let Some((x, y)) = frame.portal_start {
+        d.draw_circle_lines(
+            (x - view_x) as i32,
+            (y - view_y) as i32,
+            PORTAL_RADIUS,
+            Color::PURPLE.fade(0.5),
+        );
+    }
+
+    s2.for_each_gravity_zone(|(x, y), (hw, hh), _| {
+        d.draw_rectangle(
+            (x - hw - view_x) as i32,
+            (y - hh - view_y) as i32,
+            (hw * 2.0) as i32,
+            (hh * 2.0) as i32,
+            Color::SKYBLUE.fade(0.25),
+        );
+    });
+
+    s2.for_each_sensor(|(x, y), (hw, hh), count| {
+        let (left, top) = (x - hw - view_x, y - hh - view_y);
+        d.draw_rectangle_lines_ex(
+            Rectangle::new(left, top, hw * 2.0, hh * 2.0),
+            1.0,
+            Color::GREEN,
+        );
+        d.draw_text(
+            &count.to_string(),
+            left as i32 + 2,
+            top as i32 + 2,
+            10,
+            Color::GREEN,
+        );
+    });
+
+    /* Slanted hatch lines lean and scroll the way the belt surface moves. */
+    let time = frame.time;
+    s2.for_each_conveyor(|(x, y), (hw, hh), surface_vel, _| {
+        let (left, top) = (x - hw - view_x, y - hh - view_y);
+        let (right, bottom) = (x + hw - view_x, y + hh - view_y);
+        d.draw_rectangle_lines_ex(
+            Rectangle::new(left, top, hw * 2.0, hh * 2.0),
+            1.0,
+            Color::DARKGRAY,
+        );
+
+        let spacing = 8.0;
+        let lean = hh * 2.0 * surface_vel.signum();
+        let mut hatch = left + (time * surface_vel).rem_euclid(spacing) - spacing;
+        while hatch < right + spacing {
+            let (top_x, bottom_x) = (hatch + lean / 2.0, hatch - lean / 2.0);
+            if top_x.min(bottom_x) >= left && top_x.max(bottom_x) <= right {
+                d.draw_line_v(
+                    Vector2::new(top_x, top),
+                    Vector2::new(bottom_x, bottom),
+                    Color::DARKGRAY,
+                );
+            }
+            hatch += spacing;
+        }
+    });
+
+    if frame.laser {
+        /* A laser from the middle of the window toward the cursor. */
+        let (ox, oy) = (size.0 as f32 / 2.0, size.1 as f32 / 2.0);
+        let mouse = frame.mouse;
+        let (dx, dy) = (mouse.x - ox, mouse.y - oy);
+        let max_toi = (size.0 + size.1) as f32;
+        let toi = s2
+            .raycast(
+                screen_to_world(Vector2::new(ox, oy), (view_x, view_y)),
+                (dx, dy),
+                max_toi,
+            )
+            .map_or(max_toi, |(_, toi)| toi);
+        let length = (dx * dx + dy * dy).sqrt().max(f32::EPSILON);
+        let hit = Vector2::new(ox + dx / length * toi, oy + dy / length * toi);
+        d.draw_line_v(Vector2::new(ox, oy), hit, Color::RED);
+        d.draw_circle_v(hit, 2.0, Color::RED);
+    }
+
+    if frame.show_density {
+        const CELLS_X: usize = 16;
+        const CELLS_Y: usize = 12;
+        let grid = s2.density_grid(CELLS_X, CELLS_Y);
+        let max = grid.iter().copied().max().unwrap_or(0).max(1);
+        /* The cells tile the box in world space, as density_grid counts them. */
+        let bounds = s2.bounds();
+        let cell_width = bounds.width / CELLS_X as f32;
+        let cell_height = bounds.height / CELLS_Y as f32;
+        for (i, count) in grid.iter().enumerate() {
+            let alpha = (*count as f32 / max as f32 * 160.0) as u8;
+            d.draw_rectangle_rec(
+                Rectangle::new(
+                    bounds.x + (i % CELLS_X) as f32 * cell_width - view_x,
+                    bounds.y + (i / CELLS_X) as f32 * cell_height - view_y,
+                    cell_width,
+                    cell_height,
+                ),
+                Color::new(255, 120, 0, alpha),
+            );
+        }
+    }
+
+    if frame.show_aabbs {
+        s2.for_each_aabb(|(min_x, min_y), (max_x, max_y), _| {
+            d.draw_rectangle_lines(
+                (min_x - view_x) as i32,
+                (min_y - view_y) as i32,
+                ((max_x - min_x) as i32).max(1),
+                ((max_y - min_y) as i32).max(1),
+                Color::GREEN,
+            );
+        });
+    }
+
+    if frame.show_contacts {
+        s2.for_each_contact_point(|x, y, nx, ny| {
+            let point = Vector2::new(x - view_x, y - view_y);
+            d.draw_line_v(
+                point,
+                Vector2::new(point.x + nx * 6.0, point.y + ny * 6.0),
+                Color::MAGENTA,
+            );
+            d.draw_circle_v(point, 1.5, Color::MAGENTA);
+        });
+    }
+
+    /* Preview of what a click would spawn, drawn the same way particles are. */
+    let settings = s2.spawn_settings();
+    let mouse = frame.mouse;
+    /* Snapped and kept off the walls the same way the particle itself will be. */
+    let (x, y) = screen_to_world(mouse, frame.view);
+    let (spawn_x, spawn_y) = s2.spawn_position(x, y);
+    let spawn = Vector2::new(spawn_x - view_x, spawn_y - view_y);
+    match (frame.tool, settings.shape) {
+        (Tool::Sand, _) => d.draw_circle_lines(mouse.x as i32, mouse.y as i32, 2.0, Color::GRAY),
+        (Tool::Spin, _) => {}
+        (Tool::Particle, SpawnShape::Cuboid) => d.draw_rectangle_lines(
+            (spawn.x - settings.size) as i32,
+            (spawn.y - settings.size) as i32,
+            (settings.size * 2.0) as i32,
+            (settings.size * 2.0) as i32,
+            Color::GRAY,
+        ),
+        (Tool::Particle, SpawnShape::Ball) => {
+            d.draw_circle_lines(spawn.x as i32, spawn.y as i32, settings.size, Color::GRAY)
+        }
+        (Tool::Particle, SpawnShape::Capsule) => {
+            let radius = settings.size / 2.0;
+            for end in [-radius, radius] {
+                d.draw_circle_lines(spawn.x as i32, (spawn.y + end) as i32, radius, Color::GRAY);
+            }
+        }
+        (Tool::Particle, SpawnShape::Triangle) => {
+            let [a, b, c] =
+                triangle_points(settings.size).map(|(x, y)| Vector2::new(spawn.x + x, spawn.y + y));
+            d.draw_triangle_lines(a, b, c, Color::GRAY);
+        }
+    }
+}
+
+fn load_target(
+    rl: &mut RaylibHandle,
+    thread: &RaylibThread,
+    (width, height): (i32, i32),
+) -> Option<RenderTexture2D> {
+    match rl.load_render_texture(thread, width.max(1) as u32, height.max(1) as u32) {
+        Ok(target) => Some(target),
+        Err(err) => {
+            eprintln!("couldn't create the render texture: {err}");
+            None
+        }
+    }
+}
+
+fn load_picture(path: &str) -> Result<RgbaImage, String> {
+    let image = Image::load_image(path)?;
+    let pixels = image
+        .get_image_data()
+        .iter()
+        .map(|color| [color.r, color.g, color.b, color.a])
+        .collect();
+    RgbaImage::new(image.width() as usize, image.height() as usize, pixels)
+        .ok_or_else(|| "pixel data doesn't match the image size".to_string())
+}
+
+/* A panel beside the cursor describing the particle under it. */
+fn draw_inspector(d: &mut impl RaylibDraw, info: &ParticleInfo, mouse: Vector2) {
+    let lines = [
+        format!(
+            "#{} {}{}",
+            info.id,
+            info.shape,
+            if info.sleeping { " (asleep)" } else { "" }
+        ),
+        format!("pos {:.1}, {:.1}", info.position.0, info.position.1),
+        format!("vel {:.1}, {:.1}", info.velocity.0, info.velocity.1),
+        format!("spin {:.2} rad/s", info.angular_velocity),
+        format!("mass {:.2}", info.mass),
+    ];
+    let (x, y) = (mouse.x as i32 + 12, mouse.y as i32 + 12);
+    d.draw_rectangle(
+        x,
+        y,
+        120,
+        lines.len() as i32 * 11 + 4,
+        Color::WHITE.fade(0.85),
+    );
+    d.draw_rectangle_lines(x, y, 120, lines.len() as i32 * 11 + 4, Color::GRAY);
+    for (i, line) in lines.iter().enumerate() {
+        d.draw_text(line, x + 3, y + 3 + i as i32 * 11, 10, Color::BLACK);
+    }
+}
+
+/* Particle count in blue and step time in red, each scaled to its own peak over the window. */
+fn draw_history(d: &mut impl RaylibDraw, samples: &[Sample], area: Rectangle) {
+    d.draw_rectangle_lines_ex(area, 1.0, Color::LIGHTGRAY);
+    if samples.len() < 2 {
+        return;
+    }
+    let step = area.width / (HISTORY_LEN - 1) as f32;
+    let particles: Vec<f32> = samples
+        .iter()
+        .map(|sample| sample.particles as f32)
+        .collect();
+    let step_times: Vec<f32> = samples
+        .iter()
+        .map(|sample| sample.step_time.as_secs_f32())
+        .collect();
+    for (values, color) in [(particles, Color::BLUE), (step_times, Color::RED)] {
+        let peak = values.iter().copied().fold(f32::EPSILON, f32::max);
+        let point = |i: usize| {
+            let y = area.y + area.height * (1.0 - values[i] / peak);
+            Vector2::new(area.x + i as f32 * step, y)
+        };
+        for i in 1..values.len() {
+            d.draw_line_v(point(i - 1), point(i), color);
+        }
+    }
+}
+
+/* The world is desktop-sized and the window is a view onto it, so screen points shift by the window position. */
+fn screen_to_world(pos: Vector2, (window_x, window_y): (f32, f32)) -> (f32, f32) {
+    (pos.x + window_x, pos.y + window_y)
+}
+
+fn draw_particle(
+    d: &mut impl RaylibDraw,
+    particle: &ParticleSnapshot,
+    (offset_x, offset_y): (f32, f32),
+    color: Color,
+) {
+    let center = Vector2::new(particle.x - offset_x, particle.y - offset_y);
+    match &particle.shape {
+        ParticleShape::Cuboid => {
+            let (width, height) = (particle.width.max(1.0), particle.height.max(1.0));
+            d.draw_rectangle_pro(
+                Rectangle::new(center.x, center.y, width, height),
+                Vector2::new(width / 2.0, height / 2.0),
+                particle.angle.to_degrees(),
+                color,
             );
-        })
+        }
+        ParticleShape::Ball => d.draw_circle_v(center, (particle.width / 2.0).max(0.5), color),
+        ParticleShape::Capsule {
+            half_height,
+            radius,
+        } => {
+            let (sin, cos) = particle.angle.sin_cos();
+            let axis = Vector2::new(-sin * half_height, cos * half_height);
+            d.draw_rectangle_pro(
+                Rectangle::new(center.x, center.y, radius * 2.0, half_height * 2.0),
+                Vector2::new(*radius, *half_height),
+                particle.angle.to_degrees(),
+                color,
+            );
+            d.draw_circle_v(
+                Vector2::new(center.x + axis.x, center.y + axis.y),
+                *radius,
+                color,
+            );
+            d.draw_circle_v(
+                Vector2::new(center.x - axis.x, center.y - axis.y),
+                *radius,
+                color,
+            );
+        }
+        ParticleShape::Polygon(points) => {
+            let points: Vec<_> = points
+                .iter()
+                .map(|(x, y)| Vector2::new(x - offset_x, y - offset_y))
+                .collect();
+            draw_polygon(d, &points, color);
+        }
+        ParticleShape::RoundPolygon {
+            points,
+            border_radius,
+        } => {
+            let points: Vec<_> = points
+                .iter()
+                .map(|(x, y)| Vector2::new(x - offset_x, y - offset_y))
+                .collect();
+            draw_polygon(d, &points, color);
+
+            /* Dilate the inner polygon: a strip pushed out along each edge, and a disc on each corner. */
+            let count = points.len() as f32;
+            let centroid = points.iter().fold(Vector2::new(0.0, 0.0), |sum, point| {
+                Vector2::new(sum.x + point.x / count, sum.y + point.y / count)
+            });
+            for (i, a) in points.iter().enumerate() {
+                let b = points[(i + 1) % points.len()];
+                let (dx, dy) = (b.x - a.x, b.y - a.y);
+                let length = (dx * dx + dy * dy).sqrt().max(f32::EPSILON);
+                let mut normal = Vector2::new(dy / length, -dx / length);
+                if normal.x * (a.x - centroid.x) + normal.y * (a.y - centroid.y) < 0.0 {
+                    normal = Vector2::new(-normal.x, -normal.y);
+                }
+                let push = Vector2::new(normal.x * border_radius, normal.y * border_radius);
+                draw_quad(
+                    d,
+                    [
+                        *a,
+                        b,
+                        Vector2::new(b.x + push.x, b.y + push.y),
+                        Vector2::new(a.x + push.x, a.y + push.y),
+                    ],
+                    color,
+                );
+                d.draw_circle_v(*a, *border_radius, color);
+            }
+        }
+    }
+}
+
+fn draw_quad(d: &mut impl RaylibDraw, corners: [Vector2; 4], color: Color) {
+    draw_polygon(d, &corners, color);
+}
+
+/* raylib culls clockwise triangles, so order each triangle of the fan counter-clockwise on screen. */
+fn draw_polygon(d: &mut impl RaylibDraw, points: &[Vector2], color: Color) {
+    for i in 1..points.len().saturating_sub(1) {
+        let (a, b, c) = (points[0], points[i], points[i + 1]);
+        let cross = (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x);
+        if cross < 0.0 {
+            d.draw_triangle(a, b, c, color);
+        } else {
+            d.draw_triangle(a, c, b, color);
+        }
     }
 }
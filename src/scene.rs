@@ -0,0 +1,308 @@
+use std::{
+    fmt::Write as _,
+    io,
+    path::{Path, PathBuf},
+};
+
+use rapier2d::prelude::*;
+
+const HEADER: &str = "sandbox_xd scene 1";
+
+/* Collider geometry in the body's local frame. */
+#[derive(Clone, Debug, PartialEq)]
+pub enum SavedShape {
+    Cuboid { half_x: f32, half_y: f32 },
+    Ball { radius: f32 },
+    Capsule { half_height: f32, radius: f32 },
+    Polygon(Vec<(f32, f32)>),
+}
+
+impl SavedShape {
+    /* `None` for shapes a scene can't hold, such as terrain. */
+    pub fn from_shape(shape: &dyn Shape) -> Option<Self> {
+        let shape = match shape.as_typed_shape() {
+            TypedShape::Cuboid(c) => SavedShape::Cuboid {
+                half_x: c.half_extents.x,
+                half_y: c.half_extents.y,
+            },
+            TypedShape::Ball(b) => SavedShape::Ball { radius: b.radius },
+            TypedShape::Capsule(c) => SavedShape::Capsule {
+                half_height: c.half_height(),
+                radius: c.radius,
+            },
+            TypedShape::Triangle(t) => {
+                SavedShape::Polygon([t.a, t.b, t.c].iter().map(|p| (p.x, p.y)).collect())
+            }
+            TypedShape::ConvexPolygon(p) => {
+                SavedShape::Polygon(p.points().iter().map(|p| (p.x, p.y)).collect())
+            }
+            _ => return None,
+        };
+        Some(shape)
+    }
+
+    /* Scales about the local origin, stopping short of shrinking any extent below `min_extent`. */
+    pub fn scaled(&self, factor: f32, min_extent: f32) -> Self {
+        match self {
+            SavedShape::Cuboid { half_x, half_y } => SavedShape::Cuboid {
+                half_x: (half_x * factor).max(min_extent),
+                half_y: (half_y * factor).max(min_extent),
+            },
+            SavedShape::Ball { radius } => SavedShape::Ball {
+                radius: (radius * factor).max(min_extent),
+            },
+            SavedShape::Capsule {
+                half_height,
+                radius,
+            } => SavedShape::Capsule {
+                half_height: half_height * factor,
+                radius: (radius * factor).max(min_extent),
+            },
+            SavedShape::Polygon(points) => {
+                /* Scale uniformly so the polygon keeps its proportions at the floor. */
+                let (min, max) = points.iter().fold(
+                    ((f32::MAX, f32::MAX), (f32::MIN, f32::MIN)),
+                    |(min, max), (x, y)| {
+                        (
+                            (min.0.min(*x), min.1.min(*y)),
+                            (max.0.max(*x), max.1.max(*y)),
+                        )
+                    },
+                );
+                let smallest = ((max.0 - min.0).min(max.1 - min.1) / 2.0).max(f32::EPSILON);
+                let factor = factor.max(min_extent / smallest);
+                SavedShape::Polygon(
+                    points
+                        .iter()
+                        .map(|(x, y)| (x * factor, y * factor))
+                        .collect(),
+                )
+            }
+        }
+    }
+
+    pub fn collider(&self) -> Option<ColliderBuilder> {
+        let builder = match self {
+            SavedShape::Cuboid { half_x, half_y } => ColliderBuilder::cuboid(*half_x, *half_y),
+            SavedShape::Ball { radius } => ColliderBuilder::ball(*radius),
+            SavedShape::Capsule {
+                half_height,
+                radius,
+            } => ColliderBuilder::capsule_y(*half_height, *radius),
+            SavedShape::Polygon(points) => {
+                let points: Vec<_> = points.iter().map(|(x, y)| point![*x, *y]).collect();
+                ColliderBuilder::convex_hull(&points)?
+            }
+        };
+        Some(builder)
+    }
+}
+
+/* One dynamic particle, with enough state to rebuild it exactly. */
+#[derive(Clone, Debug, PartialEq)]
+pub struct SavedBody {
+    pub x: f32,
+    pub y: f32,
+    pub angle: f32,
+    pub linvel: (f32, f32),
+    pub angvel: f32,
+    pub user_data: u128,
+    pub friction: f32,
+    pub restitution: f32,
+    pub shape: SavedShape,
+}
+
+/* The settings a sandbox was built with, so a restored one behaves like the original. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SavedConfig {
+    pub gravity: (f32, f32),
+    /* x, y, width and height of the box. */
+    pub bounds: (f32, f32, f32, f32),
+    pub max_particles: Option<usize>,
+    pub step_hz: f32,
+    /* Resumes the random sequence where it was when the scene was taken. */
+    pub seed: u64,
+    pub target_fps: Option<u32>,
+}
+
+/* The dynamic particles of a sandbox; placed geometry and joints are not included. */
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scene {
+    pub tick: u64,
+    /* `None` in scenes saved before the settings were recorded. */
+    pub config: Option<SavedConfig>,
+    pub bodies: Vec<SavedBody>,
+}
+
+impl Scene {
+    /* One body per line; `f32` and `u128` print in a form that parses back exactly. */
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "{HEADER}");
+        let _ = writeln!(text, "tick {}", self.tick);
+        if let Some(config) = &self.config {
+            let (x, y, width, height) = config.bounds;
+            let _ = writeln!(
+                text,
+                "config {} {} {x} {y} {width} {height} {} {} {} {}",
+                config.gravity.0,
+                config.gravity.1,
+                optional(config.max_particles),
+                config.step_hz,
+                config.seed,
+                optional(config.target_fps)
+            );
+        }
+        for body in &self.bodies {
+            let _ = write!(
+                text,
+                "{} {} {} {} {} {} {} {} {}",
+                body.x,
+                body.y,
+                body.angle,
+                body.linvel.0,
+                body.linvel.1,
+                body.angvel,
+                body.user_data,
+                body.friction,
+                body.restitution
+            );
+            let _ = match &body.shape {
+                SavedShape::Cuboid { half_x, half_y } => write!(text, " cuboid {half_x} {half_y}"),
+                SavedShape::Ball { radius } => write!(text, " ball {radius}"),
+                SavedShape::Capsule {
+                    half_height,
+                    radius,
+                } => write!(text, " capsule {half_height} {radius}"),
+                SavedShape::Polygon(points) => {
+                    let _ = write!(text, " polygon {}", points.len());
+                    points
+                        .iter()
+                        .try_for_each(|(x, y)| write!(text, " {x} {y}"))
+                }
+            };
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn from_text(text: &str) -> Option<Scene> {
+        let mut lines = text.lines();
+        if lines.next()? != HEADER {
+            return None;
+        }
+        let tick = lines.next()?.strip_prefix("tick ")?.parse().ok()?;
+        let mut lines = lines.peekable();
+        let config = match lines.next_if(|line| line.starts_with("config ")) {
+            Some(line) => Some(config(line)?),
+            None => None,
+        };
+
+        let mut bodies = Vec::new();
+        for line in lines.filter(|line| !line.trim().is_empty()) {
+            let mut fields = line.split_whitespace();
+            let fields = &mut fields;
+            let (x, y, angle) = (number(fields)?, number(fields)?, number(fields)?);
+            let linvel = (number(fields)?, number(fields)?);
+            let angvel = number(fields)?;
+            let user_data = fields.next()?.parse().ok()?;
+            let (friction, restitution) = (number(fields)?, number(fields)?);
+
+            let shape = match fields.next()? {
+                "cuboid" => SavedShape::Cuboid {
+                    half_x: number(fields)?,
+                    half_y: number(fields)?,
+                },
+                "ball" => SavedShape::Ball {
+                    radius: number(fields)?,
+                },
+                "capsule" => SavedShape::Capsule {
+                    half_height: number(fields)?,
+                    radius: number(fields)?,
+                },
+                "polygon" => {
+                    let count: usize = fields.next()?.parse().ok()?;
+                    let points = (0..count)
+                        .map(|_| Some((number(fields)?, number(fields)?)))
+                        .collect::<Option<_>>()?;
+                    SavedShape::Polygon(points)
+                }
+                _ => return None,
+            };
+
+            bodies.push(SavedBody {
+                x,
+                y,
+                angle,
+                linvel,
+                angvel,
+                user_data,
+                friction,
+                restitution,
+                shape,
+            });
+        }
+        Some(Scene {
+            tick,
+            config,
+            bodies,
+        })
+    }
+
+    /* Writes beside `path` first and keeps the previous save as `<path>.1`, so a crash mid-write loses nothing. */
+    pub fn save_rotating(&self, path: &Path) -> io::Result<()> {
+        let temp = with_suffix(path, "tmp");
+        std::fs::write(&temp, self.to_text())?;
+        if path.exists() {
+            std::fs::rename(path, with_suffix(path, "1"))?;
+        }
+        std::fs::rename(temp, path)
+    }
+}
+
+fn config(line: &str) -> Option<SavedConfig> {
+    let mut fields = line.strip_prefix("config ")?.split_whitespace();
+    let fields = &mut fields;
+    let gravity = (number(fields)?, number(fields)?);
+    let bounds = (
+        number(fields)?,
+        number(fields)?,
+        number(fields)?,
+        number(fields)?,
+    );
+    let max_particles = parse_optional(fields.next()?)?;
+    let step_hz = number(fields)?;
+    let seed = fields.next()?.parse().ok()?;
+    let target_fps = parse_optional(fields.next()?)?;
+    Some(SavedConfig {
+        gravity,
+        bounds,
+        max_particles,
+        step_hz,
+        seed,
+        target_fps,
+    })
+}
+
+/* `-` stands for `None`. */
+fn optional(value: Option<impl std::fmt::Display>) -> String {
+    value.map_or_else(|| "-".to_owned(), |value| value.to_string())
+}
+
+fn parse_optional<T: std::str::FromStr>(field: &str) -> Option<Option<T>> {
+    match field {
+        "-" => Some(None),
+        field => field.parse().ok().map(Some),
+    }
+}
+
+fn number<'a>(fields: &mut impl Iterator<Item = &'a str>) -> Option<f32> {
+    fields.next()?.parse().ok()
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
@@ -1,31 +1,813 @@
-use std::time::SystemTime;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::{BufWriter, Write as _},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    sync::Arc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 
 use nalgebra::Vector2;
 use parking_lot::Mutex;
 use rapier2d::prelude::*;
 
+use crate::{
+    command::Command,
+    error::SandboxError,
+    events::{Contact, EventCollector},
+    history::{History, Sample},
+    hooks::Hooks,
+    image::RgbaImage,
+    lock::{OrderedMutex, Rank},
+    rng::Rng,
+    scene::{SavedBody, SavedConfig, SavedShape, Scene},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpawnShape {
+    Cuboid,
+    Ball,
+    Capsule,
+    Triangle,
+}
+
+impl SpawnShape {
+    pub fn next(self) -> Self {
+        match self {
+            SpawnShape::Cuboid => SpawnShape::Ball,
+            SpawnShape::Ball => SpawnShape::Capsule,
+            SpawnShape::Capsule => SpawnShape::Triangle,
+            SpawnShape::Triangle => SpawnShape::Cuboid,
+        }
+    }
+}
+
+/* Corners of the spawned triangle relative to its center: an upward-pointing equilateral with circumradius `size`. */
+pub fn triangle_points(size: f32) -> [(f32, f32); 3] {
+    [90.0f32, 210.0, 330.0].map(|degrees| {
+        let angle = degrees.to_radians();
+        (angle.cos() * size, -angle.sin() * size)
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SpawnSettings {
+    /* Half-extent of the spawned collider. */
+    pub size: f32,
+    pub shape: SpawnShape,
+    /* Spawn sleeping, to rest where placed until something wakes them. */
+    pub asleep: bool,
+    /* From 0, which doesn't bounce at all, to 1, which bounces back at full speed. */
+    pub restitution: f32,
+}
+
+impl Default for SpawnSettings {
+    fn default() -> Self {
+        Self {
+            size: 1.0,
+            shape: SpawnShape::Cuboid,
+            asleep: false,
+            restitution: 0.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParticleShape {
+    Cuboid,
+    Ball,
+    /* Aligned with the local y axis. */
+    Capsule {
+        half_height: f32,
+        radius: f32,
+    },
+    /* World-space vertices. */
+    Polygon(Vec<(f32, f32)>),
+    /* World-space vertices of the inner polygon, dilated by `border_radius`. */
+    RoundPolygon {
+        points: Vec<(f32, f32)>,
+        border_radius: f32,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParticleSnapshot {
+    pub x: f32,
+    pub y: f32,
+    /* Extents in the body's local frame. */
+    pub width: f32,
+    pub height: f32,
+    /* Rotation in radians about the center. */
+    pub angle: f32,
+    pub shape: ParticleShape,
+    /* `None` for colliders placed without a body. */
+    pub handle: Option<RigidBodyHandle>,
+    pub user_data: u128,
+    pub color: (u8, u8, u8),
+    pub restitution: f32,
+    /* Simulated seconds since it spawned. */
+    pub age: f32,
+}
+
+/* What the inspector shows about one particle, as of the last step. */
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParticleInfo {
+    /* Its spawn index, as drawn by the id overlay. */
+    pub id: u64,
+    pub position: (f32, f32),
+    pub velocity: (f32, f32),
+    pub angular_velocity: f32,
+    pub mass: f32,
+    pub shape: &'static str,
+    pub sleeping: bool,
+}
+
+/* The interior of the four walls, in world coordinates. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/* Particles slower than this stop extending their trail, so resting piles don't keep history. */
+const TRAIL_MIN_SPEED: f32 = 20.0;
+
+/* Recent positions of one fast particle, oldest first. */
+struct Trail {
+    color: (u8, u8, u8),
+    points: VecDeque<(f32, f32)>,
+}
+
+/* Each particle pulls toward at most this many neighbors per step, however crowded it is. */
+const MAX_COHESION_NEIGHBORS: usize = 8;
+
+/* A short-range pull between nearby dynamic particles. */
+#[derive(Clone, Copy, Debug)]
+struct Cohesion {
+    strength: f32,
+    radius: f32,
+}
+
+/* A side of the box. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Top,
+    Left,
+    Right,
+    Bottom,
+}
+
+/// What gravity, and the particles it acts on, are fixed to when the window
+/// moves or reshapes. In `World` gravity keeps its direction and the
+/// contents stay put on the desktop while the walls sweep past. In `Window`
+/// the contents ride along with the window, and gravity turns with the box:
+/// it keeps pointing at the same spot relative to the walls, so gravity
+/// aimed at a corner still reaches that corner after a resize. Straight
+/// down, or along either axis, is the same in both.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GravityFrame {
+    #[default]
+    World,
+    Window,
+}
+
+/// How the walls get to a new size or position when the window changes.
+/// `Teleport` puts them there at once and pulls out whatever they closed
+/// over; `Kinematic` glides them there on kinematic bodies over the next
+/// steps, so the solver sweeps particles along instead of letting a
+/// shrinking window tunnel them out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WallMoveMode {
+    #[default]
+    Teleport,
+    Kinematic,
+}
+
+/* The kinematic body a wall rides on in `WallMoveMode::Kinematic`, and where it is headed. */
+#[derive(Clone, Copy, Debug)]
+struct WallCarrier {
+    body: RigidBodyHandle,
+    target: Vector<Real>,
+}
+
+/* A steady source of particles entering from one edge; `pending` carries fractional spawns between steps. */
+#[derive(Clone, Copy, Debug)]
+struct Emitter {
+    edge: Edge,
+    rate: f32,
+    velocity: f32,
+    pending: f32,
+}
+
+/* A rectangle whose gravity replaces the global one for bodies centered inside it. */
+#[derive(Clone, Copy, Debug)]
+struct GravityZone {
+    center: Vector<Real>,
+    half: Vector<Real>,
+    gravity: Vector<Real>,
+}
+
+/* A dragged particle closes this fraction of its distance to the cursor each second, up to a top speed. */
+const DRAG_RESPONSE: f32 = 10.0;
+const DRAG_MAX_SPEED: f32 = 2000.0;
+
+/* A particle held by the cursor at `anchor`, a point in its own frame. */
+#[derive(Clone, Copy, Debug)]
+struct Drag {
+    handle: RigidBodyHandle,
+    anchor: Point<Real>,
+    target: Point<Real>,
+}
+
+/* Periodic scene saves made from the physics thread. */
+struct Autosave {
+    interval: Duration,
+    path: PathBuf,
+    last: Instant,
+}
+
+/* Runs inside `State::step` just before the pipeline, with the body set already locked. */
+pub type StepHook = Box<dyn FnMut(&mut RigidBodySet) + Send>;
+
+/* An open-top container: one kinematic body carrying its left, right and bottom walls. */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bucket {
+    pub body: RigidBodyHandle,
+    pub walls: [ColliderHandle; 3],
+}
+
+/* Portals are sensor discs this wide. */
+pub const PORTAL_RADIUS: f32 = 12.0;
+
+/* A counting zone from `State::add_sensor`. */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SensorHandle(ColliderHandle);
+
+/* Sizes of the simulation's sets; `memory_bytes` only counts their inline storage. */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Diagnostics {
+    pub bodies: usize,
+    pub colliders: usize,
+    pub joints: usize,
+    pub contacts: usize,
+    pub awake: usize,
+    pub memory_bytes: usize,
+}
+
+/* A kinematic body swinging horizontally about `origin`. */
+#[derive(Clone, Copy, Debug)]
+struct Platform {
+    handle: RigidBodyHandle,
+    origin: Vector<Real>,
+    amplitude: f32,
+    /* Seconds of simulated time per full back-and-forth. */
+    period: f32,
+}
+
+const PALETTE: [(u8, u8, u8); 12] = [
+    (230, 41, 55),
+    (255, 161, 0),
+    (253, 214, 0),
+    (0, 158, 47),
+    (0, 121, 241),
+    (135, 60, 190),
+    (255, 109, 194),
+    (127, 106, 79),
+    (0, 182, 172),
+    (190, 33, 55),
+    (102, 191, 255),
+    (80, 80, 80),
+];
+
+/* Set in `user_data` when bits 32..56 hold a chosen color rather than the palette picking one. */
+const CUSTOM_COLOR: u128 = 1 << 63;
+
+/// Colliders carry their spawn index in the upper 64 bits of `user_data`.
+/// Below that, the low 32 bits hold the tick they spawned on, and a particle
+/// given its own color keeps it in bits 32..56 with bit 63 set.
+pub fn spawn_index(user_data: u128) -> u64 {
+    (user_data >> 64) as u64
+}
+
+pub fn spawn_tick(user_data: u128) -> u32 {
+    user_data as u32
+}
+
+/* A chosen color if there is one, otherwise a stable palette color, so the Nth spawned particle always looks the same. */
+pub fn particle_color(user_data: u128) -> (u8, u8, u8) {
+    if user_data & CUSTOM_COLOR != 0 {
+        let color = (user_data >> 32) as u32;
+        return ((color >> 16) as u8, (color >> 8) as u8, color as u8);
+    }
+    PALETTE[(spawn_index(user_data) % PALETTE.len() as u64) as usize]
+}
+
+fn with_color(user_data: u128, (r, g, b): (u8, u8, u8)) -> u128 {
+    let color = (r as u128) << 48 | (g as u128) << 40 | (b as u128) << 32;
+    (user_data & !(0xFF_FFFF << 32)) | color | CUSTOM_COLOR
+}
+
+/* Applies each impulse, all scaled down together if they'd add more than `max_energy`. */
+fn apply_capped_kicks(
+    rigid_body_set: &mut RigidBodySet,
+    kicks: Vec<(RigidBodyHandle, Vector<Real>)>,
+    max_energy: f32,
+) {
+    /* Each kick adds |J|^2 / 2m to a body at rest. */
+    let energy: f32 = kicks
+        .iter()
+        .map(|(handle, kick)| kick.norm_squared() / (2.0 * rigid_body_set[*handle].mass()))
+        .sum();
+    let scale = if energy > max_energy {
+        (max_energy / energy).sqrt()
+    } else {
+        1.0
+    };
+
+    for (handle, kick) in kicks {
+        rigid_body_set[handle].apply_impulse(kick * scale, true);
+    }
+}
+
+fn spawn_collider(shape: SpawnShape, size: f32) -> ColliderBuilder {
+    match shape {
+        SpawnShape::Cuboid => ColliderBuilder::cuboid(size, size),
+        SpawnShape::Ball => ColliderBuilder::ball(size),
+        SpawnShape::Capsule => ColliderBuilder::capsule_y(size / 2.0, size / 2.0),
+        SpawnShape::Triangle => {
+            let [a, b, c] = triangle_points(size).map(|(x, y)| point![x, y]);
+            ColliderBuilder::triangle(a, b, c)
+        }
+    }
+}
+
+/* Up to `limit` dynamic bodies with a shape overlapping the circle, each listed once. */
+fn bodies_in_radius(
+    query_pipeline: &QueryPipeline,
+    rigid_body_set: &RigidBodySet,
+    collider_set: &ColliderSet,
+    center: Point<Real>,
+    radius: f32,
+    limit: usize,
+) -> Vec<RigidBodyHandle> {
+    let mut found = Vec::new();
+    query_pipeline.intersections_with_shape(
+        rigid_body_set,
+        collider_set,
+        &Isometry::translation(center.x, center.y),
+        &Ball::new(radius),
+        QueryFilter::only_dynamic(),
+        |handle| {
+            if let Some(parent) = collider_set[handle].parent() {
+                if !found.contains(&parent) {
+                    found.push(parent);
+                }
+            }
+            found.len() < limit
+        },
+    );
+    found
+}
+
+/* Impulses are gathered first so every particle sees its neighbors where they started the step. */
+fn apply_cohesion(
+    cohesion: Cohesion,
+    dt: f32,
+    query_pipeline: &QueryPipeline,
+    rigid_body_set: &mut RigidBodySet,
+    collider_set: &ColliderSet,
+) {
+    let mut impulses = Vec::new();
+    for (handle, body) in rigid_body_set.iter() {
+        if !body.is_dynamic() || body.is_sleeping() {
+            continue;
+        }
+        let center = *body.translation();
+        /* One extra slot, since the body finds itself. */
+        let neighbors = bodies_in_radius(
+            query_pipeline,
+            rigid_body_set,
+            collider_set,
+            center.into(),
+            cohesion.radius,
+            MAX_COHESION_NEIGHBORS + 1,
+        );
+        let pull = neighbors
+            .into_iter()
+            .filter(|neighbor| *neighbor != handle)
+            .filter_map(|neighbor| {
+                let offset = rigid_body_set.get(neighbor)?.translation() - center;
+                let distance = offset.norm();
+                (distance > f32::EPSILON && distance < cohesion.radius)
+                    .then(|| offset / distance * (1.0 - distance / cohesion.radius))
+            })
+            .fold(Vector::zeros(), |acc: Vector<Real>, pull| acc + pull);
+        if pull != Vector::zeros() {
+            impulses.push((handle, pull * cohesion.strength * body.mass() * dt));
+        }
+    }
+    for (handle, impulse) in impulses {
+        if let Some(body) = rigid_body_set.get_mut(handle) {
+            body.apply_impulse(impulse, true);
+        }
+    }
+}
+
+/* The nearest point to `center` where a box of `half` extents clears the walls. */
+fn inside_walls(center: Point<Real>, half: Vector<Real>, bounds: Bounds) -> Vector<Real> {
+    let min_x = bounds.x + 1.0 + half.x;
+    let min_y = bounds.y + 1.0 + half.y;
+    let max_x = (bounds.x + bounds.width - 1.0 - half.x).max(min_x);
+    let max_y = (bounds.y + bounds.height - 1.0 - half.y).max(min_y);
+    vector![center.x.clamp(min_x, max_x), center.y.clamp(min_y, max_y)]
+}
+
+/* `value` moved one `length` back inside [min, min + length) if it has just left it. */
+fn wrap_coordinate(value: f32, min: f32, length: f32) -> f32 {
+    if value < min {
+        value + length
+    } else if value >= min + length {
+        value - length
+    } else {
+        value
+    }
+}
+
+fn shape_label(shape: &dyn Shape) -> &'static str {
+    match shape.as_typed_shape() {
+        TypedShape::Ball(_) => "ball",
+        TypedShape::Cuboid(_) => "cuboid",
+        TypedShape::Capsule(_) => "capsule",
+        TypedShape::Segment(_) => "segment",
+        TypedShape::Triangle(_) => "triangle",
+        TypedShape::TriMesh(_) => "trimesh",
+        TypedShape::Polyline(_) => "polyline",
+        TypedShape::HalfSpace(_) => "halfspace",
+        TypedShape::HeightField(_) => "heightfield",
+        TypedShape::Compound(_) => "compound",
+        TypedShape::ConvexPolygon(_) => "convex polygon",
+        TypedShape::RoundCuboid(_) => "round cuboid",
+        TypedShape::RoundTriangle(_) => "round triangle",
+        TypedShape::RoundConvexPolygon(_) => "round convex polygon",
+        TypedShape::Custom(_) => "custom",
+    }
+}
+
+fn scene_from_bytes(bytes: &[u8]) -> Result<Scene, SandboxError> {
+    let text = std::str::from_utf8(bytes).map_err(|_| SandboxError::InvalidScene)?;
+    Scene::from_text(text).ok_or(SandboxError::InvalidScene)
+}
+
+fn wall_mut<'a>(
+    collider_set: &'a mut ColliderSet,
+    wall: &Mutex<ColliderHandle>,
+) -> Result<&'a mut Collider, SandboxError> {
+    collider_set
+        .get_mut(*wall.lock())
+        .ok_or(SandboxError::MissingWall)
+}
+
+/* The single group a collider belongs to, as set by `State::insert_in_group`. */
+fn collider_group(collider: &Collider) -> Option<u32> {
+    let memberships = collider.collision_groups().memberships.bits();
+    (memberships.count_ones() == 1).then(|| memberships.trailing_zeros())
+}
+
+/* What a snapshot draws for `shape` at `pose`; `None` if it is drawn elsewhere or not at all. */
+fn snapshot_shape(shape: &dyn Shape, pose: &Isometry<Real>) -> Option<(ParticleShape, f32, f32)> {
+    let drawn = match shape.as_typed_shape() {
+        TypedShape::Ball(b) => (ParticleShape::Ball, b.radius * 2.0, b.radius * 2.0),
+        TypedShape::Cuboid(c) => {
+            let half_extents = c.half_extents;
+            (
+                ParticleShape::Cuboid,
+                half_extents.x * 2.0,
+                half_extents.y * 2.0,
+            )
+        }
+        TypedShape::Capsule(c) => (
+            ParticleShape::Capsule {
+                half_height: c.half_height(),
+                radius: c.radius,
+            },
+            c.radius * 2.0,
+            (c.half_height() + c.radius) * 2.0,
+        ),
+        TypedShape::Triangle(t) => {
+            let aabb = shape.compute_aabb(pose);
+            (
+                ParticleShape::Polygon(world_points(pose, &[t.a, t.b, t.c])),
+                aabb.extents().x,
+                aabb.extents().y,
+            )
+        }
+        /* Drawn as line strips by for_each_polyline. */
+        TypedShape::Polyline(_) => return None,
+        /* Drawn as filled regions by for_each_halfspace. */
+        TypedShape::HalfSpace(_) => return None,
+        /* Drawn as terrain by for_each_heightfield. */
+        TypedShape::HeightField(_) => return None,
+        TypedShape::ConvexPolygon(polygon) => {
+            let aabb = shape.compute_aabb(pose);
+            (
+                ParticleShape::Polygon(world_points(pose, polygon.points())),
+                aabb.extents().x,
+                aabb.extents().y,
+            )
+        }
+        TypedShape::RoundCuboid(round) => {
+            let aabb = shape.compute_aabb(pose);
+            let half = round.inner_shape.half_extents;
+            let corners = [
+                point![-half.x, -half.y],
+                point![half.x, -half.y],
+                point![half.x, half.y],
+                point![-half.x, half.y],
+            ];
+            (
+                ParticleShape::RoundPolygon {
+                    points: world_points(pose, &corners),
+                    border_radius: round.border_radius,
+                },
+                aabb.extents().x,
+                aabb.extents().y,
+            )
+        }
+        TypedShape::RoundTriangle(round) => {
+            let aabb = shape.compute_aabb(pose);
+            let t = round.inner_shape;
+            (
+                ParticleShape::RoundPolygon {
+                    points: world_points(pose, &[t.a, t.b, t.c]),
+                    border_radius: round.border_radius,
+                },
+                aabb.extents().x,
+                aabb.extents().y,
+            )
+        }
+        TypedShape::RoundConvexPolygon(round) => {
+            let aabb = shape.compute_aabb(pose);
+            (
+                ParticleShape::RoundPolygon {
+                    points: world_points(pose, round.inner_shape.points()),
+                    border_radius: round.border_radius,
+                },
+                aabb.extents().x,
+                aabb.extents().y,
+            )
+        }
+        /* Nothing in the sandbox creates these, so they are skipped rather than drawn.
+         * Compounds are split into their parts before they get here. */
+        TypedShape::Segment(_)
+        | TypedShape::TriMesh(_)
+        | TypedShape::Compound(_)
+        | TypedShape::Custom(_) => return None,
+    };
+    Some(drawn)
+}
+
+fn world_points(pose: &Isometry<Real>, points: &[Point<Real>]) -> Vec<(f32, f32)> {
+    points
+        .iter()
+        .map(|point| {
+            let point = pose * point;
+            (point.x, point.y)
+        })
+        .collect()
+}
+
+fn is_convex(points: &[(f32, f32)]) -> bool {
+    let mut sign = 0.0;
+    for i in 0..points.len() {
+        let (a, b, c) = (
+            points[i],
+            points[(i + 1) % points.len()],
+            points[(i + 2) % points.len()],
+        );
+        let cross = (b.0 - a.0) * (c.1 - b.1) - (b.1 - a.1) * (c.0 - b.0);
+        if cross * sign < 0.0 {
+            return false;
+        }
+        if cross != 0.0 {
+            sign = cross;
+        }
+    }
+    true
+}
+
+/* Ear-clips a simple polygon into triangles, in either winding. */
+fn triangulate(points: &[(f32, f32)]) -> Vec<[(f32, f32); 3]> {
+    let cross = |a: (f32, f32), b: (f32, f32), c: (f32, f32)| {
+        (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0)
+    };
+    let area: f32 = (0..points.len())
+        .map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % points.len()]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum();
+    let winding = area.signum();
+
+    let mut remaining: Vec<_> = points.to_vec();
+    let mut triangles = Vec::with_capacity(points.len().saturating_sub(2));
+    while remaining.len() > 3 {
+        let count = remaining.len();
+        let ear = (0..count).find(|&i| {
+            let a = remaining[(i + count - 1) % count];
+            let b = remaining[i];
+            let c = remaining[(i + 1) % count];
+            cross(a, b, c) * winding > 0.0
+                && remaining.iter().all(|&p| {
+                    p == a
+                        || p == b
+                        || p == c
+                        || cross(a, b, p) * winding < 0.0
+                        || cross(b, c, p) * winding < 0.0
+                        || cross(c, a, p) * winding < 0.0
+                })
+        });
+        /* Self-intersecting input has no ear left; keep what was clipped so far. */
+        let Some(i) = ear else {
+            return triangles;
+        };
+        triangles.push([
+            remaining[(i + count - 1) % count],
+            remaining[i],
+            remaining[(i + 1) % count],
+        ]);
+        remaining.remove(i);
+    }
+    if remaining.len() == 3 {
+        triangles.push([remaining[0], remaining[1], remaining[2]]);
+    }
+    triangles
+}
+
+/* The inverse of an editor action, applied by `State::undo`. */
+enum UndoAction {
+    Remove(RigidBodyHandle),
+    Restore {
+        handle: RigidBodyHandle,
+        body: Box<RigidBody>,
+        colliders: Vec<Collider>,
+    },
+}
+
+const MAX_UNDO: usize = 256;
+
+/* Slow enough to count as resting for `settle`. */
+pub const SETTLED_SPEED: f32 = 0.1;
+
+/* The most kinetic energy one shake may add across the whole sandbox. */
+const MAX_SHAKE_ENERGY: f32 = 50000.0;
+/* Likewise for one implosion. */
+const MAX_IMPLODE_ENERGY: f32 = 50000.0;
+
+/* How far a gliding wall moves per step: less than the smallest particle, so none slip past. */
+const MAX_WALL_STEP: f32 = 0.5;
+
+pub const MIN_SPAWN_SIZE: f32 = 0.25;
+pub const MAX_SPAWN_SIZE: f32 = 64.0;
+
+/* Methods needing several physics sets must lock them in `lock::Rank` order. */
 pub struct State {
-    rigid_body_set: Mutex<RigidBodySet>,
-    collider_set: Mutex<ColliderSet>,
-    gravity: Vector2<Real>,
-    integration_parameters: IntegrationParameters,
-    physics_pipeline: Mutex<PhysicsPipeline>,
-    island_manager: Mutex<IslandManager>,
-    broad_phase: Mutex<DefaultBroadPhase>,
-    narrow_phase: Mutex<NarrowPhase>,
-    impulse_joint_set: Mutex<ImpulseJointSet>,
-    multibody_joint_set: Mutex<MultibodyJointSet>,
-    ccd_solver: Mutex<CCDSolver>,
-    query_pipeline: Mutex<QueryPipeline>,
+    rigid_body_set: OrderedMutex<RigidBodySet>,
+    collider_set: OrderedMutex<ColliderSet>,
+    gravity: Mutex<Vector2<Real>>,
+    wind: Mutex<Vector2<Real>>,
+    quadratic_drag: Mutex<f32>,
+    cohesion: Mutex<Option<Cohesion>>,
+    drag: Mutex<Option<Drag>>,
+    step_hook: Mutex<Option<StepHook>>,
+    integration_parameters: Mutex<IntegrationParameters>,
+    step_hz: f32,
+    max_particles: Option<usize>,
+    target_fps: Option<u32>,
+    rng: Mutex<Rng>,
+    physics_pipeline: OrderedMutex<PhysicsPipeline>,
+    island_manager: OrderedMutex<IslandManager>,
+    broad_phase: OrderedMutex<DefaultBroadPhase>,
+    narrow_phase: OrderedMutex<NarrowPhase>,
+    impulse_joint_set: OrderedMutex<ImpulseJointSet>,
+    multibody_joint_set: OrderedMutex<MultibodyJointSet>,
+    ccd_solver: OrderedMutex<CCDSolver>,
+    query_pipeline: OrderedMutex<QueryPipeline>,
+    events: EventCollector,
+    history: History,
+    hooks: Hooks,
 
     box_left: Mutex<ColliderHandle>,
     box_right: Mutex<ColliderHandle>,
     box_top: Mutex<ColliderHandle>,
     box_bottom: Mutex<ColliderHandle>,
+    bounds: Mutex<Bounds>,
+    /* Set while the walls hold a fixed size instead of following the window. */
+    arena: Mutex<Option<(f32, f32)>>,
+    gravity_frame: Mutex<GravityFrame>,
+    wall_material: Mutex<Option<(f32, f32)>>,
+    /* One per wall, in `walls` order, while they glide; empty while they teleport. */
+    wall_carriers: Mutex<Vec<WallCarrier>>,
+    /* Opposite edges joined instead of walled; see `set_wrap`. */
+    wrap: AtomicBool,
+
+    spawn_settings: Mutex<SpawnSettings>,
+    step_time: Mutex<Duration>,
+    /* When the last step finished, and where each moving collider was before it. */
+    last_step: Mutex<Instant>,
+    previous_poses: Mutex<HashMap<ColliderHandle, Isometry<Real>>>,
+    step_budget: Mutex<Option<Duration>>,
+    over_budget: AtomicBool,
+    physics_paused: AtomicBool,
+    render_paused: AtomicBool,
+    #[cfg(feature = "parallel")]
+    parallel: AtomicBool,
+    /* rapier spreads its work over whichever rayon pool it runs in. */
+    #[cfg(feature = "parallel")]
+    serial_pool: rayon::ThreadPool,
+    tick: AtomicU64,
+    spawn_index: AtomicU64,
+    max_speed: Mutex<Option<f32>>,
+    max_spawn_speed: Mutex<Option<f32>>,
+    grid_snap: Mutex<Option<f32>>,
+    pour_rate: Mutex<f32>,
+    platforms: Mutex<Vec<Platform>>,
+    emitters: Mutex<Vec<Emitter>>,
+    gravity_zones: Mutex<Vec<GravityZone>>,
+    /* Each portal mapped to its partner. */
+    portals: Mutex<HashMap<ColliderHandle, ColliderHandle>>,
+    /* Bodies sent through to a portal and still inside it, which mustn't be sent straight back. */
+    portal_arrivals: Mutex<HashSet<(ColliderHandle, RigidBodyHandle)>>,
+    commands: Mutex<VecDeque<Command>>,
+    undo_stack: Mutex<Vec<UndoAction>>,
+    autosave: Mutex<Option<Autosave>>,
+    trail_length: Mutex<usize>,
+    trails: Mutex<HashMap<RigidBodyHandle, Trail>>,
+    /* Labels from `set_tag`, such as "metal", for the tools that pick particles by type. */
+    tags: Mutex<HashMap<RigidBodyHandle, String>>,
 }
 
-impl State {
-    pub fn new() -> Self {
+pub struct StateBuilder {
+    gravity: Vector2<Real>,
+    width: f32,
+    height: f32,
+    max_particles: Option<usize>,
+    step_hz: f32,
+    seed: u64,
+    target_fps: Option<u32>,
+}
+
+impl Default for StateBuilder {
+    fn default() -> Self {
+        Self {
+            gravity: vector![0.0, 9.81],
+            width: 320.0,
+            height: 240.0,
+            max_particles: None,
+            step_hz: 60.0,
+            seed: 0,
+            target_fps: Some(60),
+        }
+    }
+}
+
+impl StateBuilder {
+    pub fn gravity(mut self, gravity: Vector2<Real>) -> Self {
+        self.gravity = gravity;
+        self
+    }
+
+    pub fn bounds(mut self, width: f32, height: f32) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    pub fn max_particles(mut self, max_particles: usize) -> Self {
+        self.max_particles = Some(max_particles);
+        self
+    }
+
+    /* Sets the physics timestep to `1 / step_hz` seconds. */
+    pub fn step_hz(mut self, step_hz: f32) -> Self {
+        self.step_hz = step_hz.max(1.0);
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /* Frame cap for the render loop; `None` syncs to the display instead. Headless runs ignore it. */
+    pub fn target_fps(mut self, target_fps: Option<u32>) -> Self {
+        self.target_fps = target_fps;
+        self
+    }
+
+    pub fn build(self) -> State {
         let rigid_body_set = RigidBodySet::new();
         let mut collider_set = ColliderSet::new();
 
@@ -48,8 +830,11 @@ impl State {
         let box_right = collider_set.insert(box_right);
 
         /* Create other structures necessary for the simulation. */
-        let gravity = vector![0.0, 9.81];
-        let integration_parameters = IntegrationParameters::default();
+        let gravity = self.gravity;
+        let integration_parameters = IntegrationParameters {
+            dt: 1.0 / self.step_hz,
+            ..IntegrationParameters::default()
+        };
         let physics_pipeline = PhysicsPipeline::new();
         let island_manager = IslandManager::new();
         let broad_phase = DefaultBroadPhase::new();
@@ -58,33 +843,334 @@ impl State {
         let multibody_joint_set = MultibodyJointSet::new();
         let ccd_solver = CCDSolver::new();
         let query_pipeline = QueryPipeline::new();
-        let physics_hooks = ();
-        let event_handler = ();
 
-        Self {
-            rigid_body_set: Mutex::new(rigid_body_set),
-            collider_set: Mutex::new(collider_set),
-            gravity: gravity,
-            integration_parameters: integration_parameters,
-            physics_pipeline: Mutex::new(physics_pipeline),
-            island_manager: Mutex::new(island_manager),
-            broad_phase: Mutex::new(broad_phase),
-            narrow_phase: Mutex::new(narrow_phase),
-            impulse_joint_set: Mutex::new(impulse_joint_set),
-            multibody_joint_set: Mutex::new(multibody_joint_set),
-            ccd_solver: Mutex::new(ccd_solver),
-            query_pipeline: Mutex::new(query_pipeline),
+        let state = State {
+            rigid_body_set: OrderedMutex::new(Rank::RigidBodySet, rigid_body_set),
+            collider_set: OrderedMutex::new(Rank::ColliderSet, collider_set),
+            gravity: Mutex::new(gravity),
+            wind: Mutex::new(Vector2::zeros()),
+            quadratic_drag: Mutex::new(0.0),
+            cohesion: Mutex::new(None),
+            drag: Mutex::new(None),
+            step_hook: Mutex::new(None),
+            integration_parameters: Mutex::new(integration_parameters),
+            step_hz: self.step_hz,
+            max_particles: self.max_particles,
+            target_fps: self.target_fps,
+            rng: Mutex::new(Rng::new(self.seed)),
+            physics_pipeline: OrderedMutex::new(Rank::PhysicsPipeline, physics_pipeline),
+            island_manager: OrderedMutex::new(Rank::IslandManager, island_manager),
+            broad_phase: OrderedMutex::new(Rank::BroadPhase, broad_phase),
+            narrow_phase: OrderedMutex::new(Rank::NarrowPhase, narrow_phase),
+            impulse_joint_set: OrderedMutex::new(Rank::ImpulseJointSet, impulse_joint_set),
+            multibody_joint_set: OrderedMutex::new(Rank::MultibodyJointSet, multibody_joint_set),
+            ccd_solver: OrderedMutex::new(Rank::CcdSolver, ccd_solver),
+            query_pipeline: OrderedMutex::new(Rank::QueryPipeline, query_pipeline),
+            events: EventCollector::default(),
+            history: History::default(),
+            hooks: Hooks::default(),
             box_left: Mutex::new(box_left),
             box_right: Mutex::new(box_right),
             box_top: Mutex::new(box_top),
             box_bottom: Mutex::new(box_bottom),
+            bounds: Mutex::new(Bounds {
+                x: 0.0,
+                y: 0.0,
+                width: self.width,
+                height: self.height,
+            }),
+            wall_material: Mutex::new(None),
+            wall_carriers: Mutex::new(Vec::new()),
+            wrap: AtomicBool::new(false),
+            arena: Mutex::new(None),
+            gravity_frame: Mutex::new(GravityFrame::World),
+            spawn_settings: Mutex::new(SpawnSettings::default()),
+            step_time: Mutex::new(Duration::ZERO),
+            last_step: Mutex::new(Instant::now()),
+            previous_poses: Mutex::new(HashMap::new()),
+            step_budget: Mutex::new(None),
+            over_budget: AtomicBool::new(false),
+            physics_paused: AtomicBool::new(false),
+            render_paused: AtomicBool::new(false),
+            #[cfg(feature = "parallel")]
+            parallel: AtomicBool::new(false),
+            #[cfg(feature = "parallel")]
+            serial_pool: rayon::ThreadPoolBuilder::new()
+                .num_threads(1)
+                .build()
+                .expect("failed to start the serial physics pool"),
+            tick: AtomicU64::new(0),
+            /* Index 0 is skipped so no particle shares the walls' `user_data` of 0. */
+            spawn_index: AtomicU64::new(1),
+            max_speed: Mutex::new(None),
+            max_spawn_speed: Mutex::new(None),
+            grid_snap: Mutex::new(None),
+            pour_rate: Mutex::new(30.0),
+            platforms: Mutex::new(Vec::new()),
+            emitters: Mutex::new(Vec::new()),
+            gravity_zones: Mutex::new(Vec::new()),
+            portals: Mutex::new(HashMap::new()),
+            portal_arrivals: Mutex::new(HashSet::new()),
+            commands: Mutex::new(VecDeque::new()),
+            undo_stack: Mutex::new(Vec::new()),
+            autosave: Mutex::new(None),
+            trail_length: Mutex::new(0),
+            trails: Mutex::new(HashMap::new()),
+            tags: Mutex::new(HashMap::new()),
+        };
+
+        /* The walls were only just inserted, so none can be missing. */
+        let _ = state.resize(0.0, 0.0, self.width, self.height);
+        state
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl State {
+    pub fn new() -> Self {
+        StateBuilder::default().build()
+    }
+
+    /// Lets `f` change any of rapier's solver settings, such as `dt`,
+    /// iteration counts or contact stiffness. Changes apply from the next
+    /// step.
+    ///
+    /// `dt` is how much simulated time each step covers, while the physics
+    /// thread still steps `step_hz` times a second, so raising `dt` alone
+    /// speeds the simulation up relative to the wall clock. A `dt` that isn't
+    /// positive is put back as it was.
+    pub fn with_integration_parameters(&self, f: impl FnOnce(&mut IntegrationParameters)) {
+        let mut integration_parameters = self.integration_parameters.lock();
+        let dt = integration_parameters.dt;
+        f(&mut integration_parameters);
+        if integration_parameters.dt.is_nan() || integration_parameters.dt <= 0.0 {
+            integration_parameters.dt = dt;
+        }
+    }
+
+    pub fn step_hz(&self) -> f32 {
+        self.step_hz
+    }
+
+    pub fn target_fps(&self) -> Option<u32> {
+        self.target_fps
+    }
+
+    /* Draws from the state's seeded generator. */
+    pub fn random_range(&self, min: f32, max: f32) -> f32 {
+        self.rng.lock().range(min, max)
+    }
+
+    pub fn particle_count(&self) -> usize {
+        self.rigid_body_set
+            .lock()
+            .iter()
+            .filter(|(_, body)| body.is_dynamic())
+            .count()
+    }
+
+    /* The mass-weighted mean of every particle's center of mass; `None` when the box is empty. */
+    pub fn center_of_mass(&self) -> Option<(f32, f32)> {
+        let rigid_body_set = self.rigid_body_set.lock();
+        let (total, weighted) = rigid_body_set
+            .iter()
+            .filter(|(_, body)| body.is_dynamic())
+            .fold((0.0, Vector::zeros()), |(total, weighted), (_, body)| {
+                let mass = body.mass();
+                (total + mass, weighted + body.center_of_mass().coords * mass)
+            });
+        (total > 0.0).then(|| {
+            let center: Vector<Real> = weighted / total;
+            (center.x, center.y)
+        })
+    }
+
+    /* How many colliders of each shape kind exist, the walls included. */
+    pub fn shape_histogram(&self) -> HashMap<&'static str, usize> {
+        let mut histogram = HashMap::new();
+        for (_, collider) in self.collider_set.lock().iter() {
+            *histogram.entry(shape_label(collider.shape())).or_insert(0) += 1;
+        }
+        histogram
+    }
+
+    /* The summed area of every dynamic collider; overlaps between a body's own colliders count twice. */
+    pub fn total_particle_area(&self) -> f32 {
+        let rigid_body_set = self.rigid_body_set.lock();
+        let collider_set = self.collider_set.lock();
+        rigid_body_set
+            .iter()
+            .filter(|(_, body)| body.is_dynamic())
+            .flat_map(|(_, body)| body.colliders())
+            .filter_map(|collider| collider_set.get(*collider))
+            /* At unit density a 2D shape's mass is its area. */
+            .map(|collider| collider.shape().mass_properties(1.0).mass())
+            .sum()
+    }
+
+    /* Counts particle centers per cell over the current bounds, row-major from the top-left. */
+    pub fn density_grid(&self, cells_x: usize, cells_y: usize) -> Vec<u32> {
+        let mut grid = vec![0; cells_x * cells_y];
+        if grid.is_empty() {
+            return grid;
+        }
+
+        let bounds = self.bounds();
+        let cell_width = bounds.width / cells_x as f32;
+        let cell_height = bounds.height / cells_y as f32;
+        for (_, body) in self.rigid_body_set.lock().iter() {
+            if !body.is_dynamic() {
+                continue;
+            }
+            let pos = body.translation();
+            let cx = ((pos.x - bounds.x) / cell_width).floor();
+            let cy = ((pos.y - bounds.y) / cell_height).floor();
+            if cx >= 0.0 && cy >= 0.0 && (cx as usize) < cells_x && (cy as usize) < cells_y {
+                grid[cy as usize * cells_x + cx as usize] += 1;
+            }
+        }
+        grid
+    }
+
+    /* True once every dynamic body is asleep or moving slower than `speed_eps`. */
+    pub fn is_settled(&self, speed_eps: f32) -> bool {
+        self.rigid_body_set
+            .lock()
+            .iter()
+            .filter(|(_, body)| body.is_dynamic())
+            .all(|(_, body)| body.is_sleeping() || body.linvel().norm() < speed_eps)
+    }
+
+    /// Steps until `is_settled(SETTLED_SPEED)` holds or `max_steps` steps
+    /// have run, returning whether it settled. It checks only after
+    /// stepping, since particles that were just spawned are still but not
+    /// yet resting. Meant for driving a sandbox by hand, as in tests; with the
+    /// physics thread also stepping, the count covers only the steps taken
+    /// here.
+    pub fn settle(&self, max_steps: usize) -> bool {
+        for _ in 0..max_steps {
+            self.step();
+            if self.is_settled(SETTLED_SPEED) {
+                return true;
+            }
+        }
+        self.is_settled(SETTLED_SPEED)
+    }
+
+    pub fn gravity(&self) -> Vector2<Real> {
+        *self.gravity.lock()
+    }
+
+    /* Which way is down, as a unit vector; `(0.0, 0.0)` with gravity off. */
+    pub fn gravity_direction(&self) -> (f32, f32) {
+        let gravity = self.gravity();
+        match gravity.try_normalize(f32::EPSILON) {
+            Some(direction) => (direction.x, direction.y),
+            None => (0.0, 0.0),
+        }
+    }
+
+    pub fn set_gravity(&self, gravity: Vector2<Real>) {
+        *self.gravity.lock() = gravity;
+    }
+
+    pub fn wind(&self) -> Vector2<Real> {
+        *self.wind.lock()
+    }
+
+    /* A force per unit of exposed length, so small or light particles are blown harder than heavy ones. */
+    pub fn set_wind(&self, wind: Vector2<Real>) {
+        *self.wind.lock() = wind;
+    }
+
+    /// Air resistance that grows with the square of speed: each dynamic body
+    /// feels `-coeff * |v| * v`, so light particles settle at a low terminal
+    /// velocity while heavy ones keep falling fast. Zero, the default, or
+    /// anything non-finite or negative turns it off.
+    pub fn set_quadratic_drag(&self, coeff: f32) {
+        *self.quadratic_drag.lock() = if coeff.is_finite() {
+            coeff.max(0.0)
+        } else {
+            0.0
+        };
+    }
+
+    /// Makes particles within `radius` of each other pull together, each
+    /// gaining up to `strength` units/s of speed per second toward every
+    /// neighbor, fading to nothing at the edge of the radius. Piles clump and
+    /// flow like a thick liquid. A non-positive strength or radius turns it off.
+    pub fn set_cohesion(&self, strength: f32, radius: f32) {
+        *self.cohesion.lock() =
+            (strength > 0.0 && radius > 0.0).then_some(Cohesion { strength, radius });
+    }
+
+    /* Replaces any previous hook. The hook must not call back into `State`, which is mid-step. */
+    pub fn set_step_hook(&self, hook: StepHook) {
+        *self.step_hook.lock() = Some(hook);
+    }
+
+    pub fn clear_step_hook(&self) {
+        *self.step_hook.lock() = None;
+    }
+
+    pub fn enqueue_command(&self, command: Command) {
+        self.commands.lock().push_back(command);
+    }
+
+    fn apply_command(&self, command: Command) {
+        match command {
+            Command::Spawn { x, y } => {
+                if let Some(handle) = self.insert_current(x, y) {
+                    self.push_undo(UndoAction::Remove(handle));
+                }
+            }
+            Command::Remove { x, y } => {
+                if let Some(handle) = self.particle_at(x, y) {
+                    if let Ok((body, colliders)) = self.take(handle) {
+                        self.push_undo(UndoAction::Restore {
+                            handle,
+                            body: Box::new(body),
+                            colliders,
+                        });
+                    }
+                }
+            }
+            Command::Impulse { x, y, impulse } => {
+                if let Some(handle) = self.particle_at(x, y) {
+                    if let Some(body) = self.rigid_body_set.lock().get_mut(handle) {
+                        body.apply_impulse(impulse, true);
+                    }
+                }
+            }
+            Command::SetGravity { gravity } => self.set_gravity(gravity),
+            Command::Clear => self.reset(),
+            Command::TogglePause => self.set_physics_paused(!self.is_physics_paused()),
         }
     }
 
     pub fn step(&self) {
+        let start = Instant::now();
+
+        let commands: Vec<_> = self.commands.lock().drain(..).collect();
+        for command in commands {
+            self.apply_command(command);
+        }
+        /* Commands still run while paused, so one can unpause. */
+        if self.is_physics_paused() {
+            return;
+        }
+
+        self.autosave_if_due();
+        self.run_emitters();
+
         let mut physics_pipeline = self.physics_pipeline.lock();
-        let gravity = self.gravity;
-        let integration_parameters = self.integration_parameters;
+        let gravity = self.gravity();
+        let integration_parameters = *self.integration_parameters.lock();
         let mut island_manager = self.island_manager.lock();
         let mut broad_phase = self.broad_phase.lock();
         let mut narrow_phase = self.narrow_phase.lock();
@@ -95,91 +1181,2942 @@ impl State {
         let mut ccd_solver = self.ccd_solver.lock();
         let mut query_pipeline = self.query_pipeline.lock();
 
-        physics_pipeline.step(
-            &gravity,
-            &integration_parameters,
-            &mut island_manager,
-            &mut *broad_phase,
-            &mut narrow_phase,
-            &mut rigid_body_set,
-            &mut collider_set,
-            &mut impulse_joint_set,
-            &mut multibody_joint_set,
-            &mut ccd_solver,
-            Some(&mut query_pipeline),
-            &(),
-            &(),
-        );
-    }
+        self.glide_walls(&mut rigid_body_set);
 
-    pub fn insert_particle(&self, x: f32, y: f32) {
-        let mut rigid_body_set = self.rigid_body_set.lock();
-        let mut collider_set = self.collider_set.lock();
+        let time = (self.tick() + 1) as f32 * integration_parameters.dt;
+        for platform in self.platforms.lock().iter() {
+            if let Some(body) = rigid_body_set.get_mut(platform.handle) {
+                let phase = time / platform.period * std::f32::consts::TAU;
+                let offset = vector![platform.amplitude * phase.sin(), 0.0];
+                body.set_next_kinematic_translation(platform.origin + offset);
+            }
+        }
 
-        let mut rigid_body = RigidBodyBuilder::dynamic()
-            .translation(vector![x, y])
-            .build();
+        let wind = self.wind();
+        if let Some(direction) = wind.try_normalize(f32::EPSILON) {
+            for (_, body) in rigid_body_set.iter_mut() {
+                if !body.is_dynamic() {
+                    continue;
+                }
+                /* The exposed cross-section is the body's extent across the wind. */
+                let exposed: f32 = body
+                    .colliders()
+                    .iter()
+                    .filter_map(|collider| collider_set.get(*collider))
+                    .map(|collider| {
+                        let extents = collider.compute_aabb().extents();
+                        direction.x.abs() * extents.y + direction.y.abs() * extents.x
+                    })
+                    .sum();
+                body.apply_impulse(wind * exposed * integration_parameters.dt, true);
+            }
+        }
 
-        rigid_body.wake_up(true);
-        let mut collider = ColliderBuilder::cuboid(1.0, 1.0).restitution(-1.0).build();
+        let gravity_zones = self.gravity_zones.lock().clone();
+        if !gravity_zones.is_empty() {
+            for (_, body) in rigid_body_set.iter_mut() {
+                if !body.is_dynamic() {
+                    continue;
+                }
+                let offset = body.center_of_mass().coords;
+                let Some(zone) = gravity_zones.iter().rev().find(|zone| {
+                    let d = offset - zone.center;
+                    d.x.abs() <= zone.half.x && d.y.abs() <= zone.half.y
+                }) else {
+                    continue;
+                };
+                /* The pipeline still applies global gravity, so add only the difference. */
+                let change = (zone.gravity - gravity) * body.gravity_scale();
+                body.apply_impulse(change * body.mass() * integration_parameters.dt, true);
+            }
+        }
 
-        collider.user_data = SystemTime::UNIX_EPOCH.elapsed().unwrap().as_nanos();
+        let quadratic_drag = *self.quadratic_drag.lock();
+        if quadratic_drag > 0.0 {
+            for (_, body) in rigid_body_set.iter_mut() {
+                if !body.is_dynamic() || body.mass() <= 0.0 {
+                    continue;
+                }
+                /* Solved implicitly, so a tiny particle at high speed slows rather than reversing. */
+                let linvel = *body.linvel();
+                let k = quadratic_drag * linvel.norm() * integration_parameters.dt / body.mass();
+                body.apply_impulse(-linvel * body.mass() * (k / (1.0 + k)), false);
+            }
+        }
 
-        let ball_body_handle = rigid_body_set.insert(rigid_body);
-        collider_set.insert_with_parent(collider, ball_body_handle, &mut rigid_body_set);
-    }
+        self.apply_drag(&mut rigid_body_set);
 
-    pub fn resize(&self, x: f32, y: f32, width: f32, height: f32) {
+        if let Some(cohesion) = *self.cohesion.lock() {
+            apply_cohesion(
+                cohesion,
+                integration_parameters.dt,
+                &query_pipeline,
+                &mut rigid_body_set,
+                &collider_set,
+            );
+        }
+
+        if let Some(hook) = self.step_hook.lock().as_mut() {
+            hook(&mut rigid_body_set);
+        }
+
+        {
+            let mut previous_poses = self.previous_poses.lock();
+            previous_poses.clear();
+            /* Taken from the bodies, since colliders only catch up with teleports during the step. */
+            previous_poses.extend(collider_set.iter().filter_map(|(handle, collider)| {
+                let body = rigid_body_set.get(collider.parent()?)?;
+                let pose = body.position() * collider.position_wrt_parent()?;
+                (!body.is_fixed()).then_some((handle, pose))
+            }));
+        }
+
+        /* Plain references, since the guards themselves can't be sent to a pool thread. */
+        let physics_pipeline: &mut PhysicsPipeline = &mut physics_pipeline;
+        let island_manager: &mut IslandManager = &mut island_manager;
+        let broad_phase: &mut DefaultBroadPhase = &mut broad_phase;
+        let narrow_phase: &mut NarrowPhase = &mut narrow_phase;
+        let bodies: &mut RigidBodySet = &mut rigid_body_set;
+        let colliders: &mut ColliderSet = &mut collider_set;
+        let impulse_joints: &mut ImpulseJointSet = &mut impulse_joint_set;
+        let multibody_joints: &mut MultibodyJointSet = &mut multibody_joint_set;
+        let ccd_solver: &mut CCDSolver = &mut ccd_solver;
+        let queries: &mut QueryPipeline = &mut query_pipeline;
+        self.in_step_pool(|| {
+            physics_pipeline.step(
+                &gravity,
+                &integration_parameters,
+                island_manager,
+                broad_phase,
+                narrow_phase,
+                bodies,
+                colliders,
+                impulse_joints,
+                multibody_joints,
+                ccd_solver,
+                Some(queries),
+                &self.hooks,
+                &self.events,
+            )
+        });
+
+        if let Some(max_speed) = *self.max_speed.lock() {
+            for (_, body) in rigid_body_set.iter_mut() {
+                if body.is_dynamic() && body.linvel().norm() > max_speed {
+                    let linvel = body.linvel().normalize() * max_speed;
+                    body.set_linvel(linvel, false);
+                }
+            }
+        }
+
+        self.cross_portals(&mut rigid_body_set, &collider_set);
+        self.wrap_edges(&mut rigid_body_set, &impulse_joint_set);
+        self.record_trails(&rigid_body_set, &collider_set);
+
+        let tick = self.tick.fetch_add(1, Ordering::Relaxed) + 1;
+        *self.last_step.lock() = Instant::now();
+        let elapsed = start.elapsed();
+        *self.step_time.lock() = elapsed;
+
+        let (particles, kinetic_energy) = rigid_body_set
+            .iter()
+            .filter(|(_, body)| body.is_dynamic())
+            .fold((0, 0.0), |(count, energy), (_, body)| {
+                (count + 1, energy + body.kinetic_energy())
+            });
+        self.history.push(Sample {
+            tick,
+            particles,
+            step_time: elapsed,
+            kinetic_energy,
+        });
+
+        /* Warn once per overload, and only clear it once steps are comfortably back under. */
+        if let Some(budget) = *self.step_budget.lock() {
+            if elapsed > budget {
+                if !self.over_budget.swap(true, Ordering::Relaxed) {
+                    eprintln!("step took {elapsed:?}, over its {budget:?} budget");
+                }
+            } else if elapsed < budget / 2 {
+                self.over_budget.store(false, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /* Clamps every dynamic body's speed after each step; `f32::INFINITY` disables it. */
+    pub fn set_max_speed(&self, max_speed: f32) {
+        *self.max_speed.lock() = max_speed.is_finite().then(|| max_speed.max(0.0));
+    }
+
+    /* Caps only the speed particles are launched with, the way `set_max_speed` caps it every step. */
+    pub fn set_max_spawn_speed(&self, max_speed: f32) {
+        *self.max_spawn_speed.lock() = max_speed.is_finite().then(|| max_speed.max(0.0));
+    }
+
+    pub fn is_physics_paused(&self) -> bool {
+        self.physics_paused.load(Ordering::Relaxed)
+    }
+
+    /* While paused, `step` only applies queued commands. */
+    pub fn set_physics_paused(&self, paused: bool) {
+        self.physics_paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn is_render_paused(&self) -> bool {
+        self.render_paused.load(Ordering::Relaxed)
+    }
+
+    /* Physics carries on; only the window stops picking up new particle positions. */
+    pub fn set_render_paused(&self, paused: bool) {
+        self.render_paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn tick(&self) -> u64 {
+        self.tick.load(Ordering::Relaxed)
+    }
+
+    /* Removes every particle and piece of placed geometry, keeping the walls. */
+    pub fn reset(&self) {
+        let mut island_manager = self.island_manager.lock();
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let mut collider_set = self.collider_set.lock();
+        let mut impulse_joint_set = self.impulse_joint_set.lock();
+        let mut multibody_joint_set = self.multibody_joint_set.lock();
+
+        let carriers: Vec<_> = self.wall_carriers.lock().iter().map(|c| c.body).collect();
+        let bodies: Vec<_> = rigid_body_set
+            .iter()
+            .map(|(handle, _)| handle)
+            .filter(|handle| !carriers.contains(handle))
+            .collect();
+        for handle in bodies {
+            rigid_body_set.remove(
+                handle,
+                &mut island_manager,
+                &mut collider_set,
+                &mut impulse_joint_set,
+                &mut multibody_joint_set,
+                true,
+            );
+        }
+
+        let colliders: Vec<_> = collider_set
+            .iter()
+            .map(|(handle, _)| handle)
+            .filter(|handle| !self.is_wall(*handle))
+            .collect();
+        for handle in colliders {
+            collider_set.remove(handle, &mut island_manager, &mut rigid_body_set, false);
+        }
+
+        self.platforms.lock().clear();
+        self.emitters.lock().clear();
+        self.gravity_zones.lock().clear();
+        self.events.clear_sensors();
+        self.events.clear_portals();
+        self.portals.lock().clear();
+        self.portal_arrivals.lock().clear();
+        self.hooks.clear();
+        self.undo_stack.lock().clear();
+        self.trails.lock().clear();
+        self.tags.lock().clear();
+        self.history.clear();
+        self.tick.store(0, Ordering::Relaxed);
+        self.spawn_index.store(1, Ordering::Relaxed);
+    }
+
+    /* Saves to `path` every `interval` of wall time while stepping; `None` turns it off. */
+    pub fn set_autosave(&self, interval: Option<Duration>, path: PathBuf) {
+        *self.autosave.lock() = interval.map(|interval| Autosave {
+            interval,
+            path,
+            last: Instant::now(),
+        });
+    }
+
+    /// Adds a source that spawns `rate` particles per simulated second at
+    /// random points along `edge`, each moving into the box at `velocity`.
+    /// Emitters run alongside each other and stop spawning while the sandbox
+    /// is at capacity.
+    pub fn add_emitter(&self, edge: Edge, rate: f32, velocity: f32) {
+        self.emitters.lock().push(Emitter {
+            edge,
+            rate: rate.max(0.0),
+            velocity,
+            pending: 0.0,
+        });
+    }
+
+    pub fn clear_emitters(&self) {
+        self.emitters.lock().clear();
+    }
+
+    /// Adds a `w` by `h` region centered on (x, y) where `gravity` acts
+    /// instead of the global gravity on any dynamic body whose center is
+    /// inside, so particles can float up or fall sideways through it. Where
+    /// zones overlap, the one added last wins.
+    pub fn add_gravity_zone(&self, x: f32, y: f32, w: f32, h: f32, gravity: Vector2<Real>) {
+        self.gravity_zones.lock().push(GravityZone {
+            center: vector![x, y],
+            half: vector![w.abs() / 2.0, h.abs() / 2.0],
+            gravity,
+        });
+    }
+
+    pub fn clear_gravity_zones(&self) {
+        self.gravity_zones.lock().clear();
+    }
+
+    /* Reports each zone's center, half extents and gravity. */
+    pub fn for_each_gravity_zone(&self, mut func: impl FnMut((f32, f32), (f32, f32), (f32, f32))) {
+        for zone in self.gravity_zones.lock().iter() {
+            func(
+                (zone.center.x, zone.center.y),
+                (zone.half.x, zone.half.y),
+                (zone.gravity.x, zone.gravity.y),
+            );
+        }
+    }
+
+    /* Positions are picked under the emitter lock, but spawning waits until it is released. */
+    fn run_emitters(&self) {
+        let dt = self.integration_parameters.lock().dt;
+        let bounds = self.bounds();
+        let mut spawns = Vec::new();
+        for emitter in self.emitters.lock().iter_mut() {
+            emitter.pending += emitter.rate * dt;
+            while emitter.pending >= 1.0 {
+                emitter.pending -= 1.0;
+                let along_x = self.random_range(bounds.x, bounds.x + bounds.width);
+                let along_y = self.random_range(bounds.y, bounds.y + bounds.height);
+                let v = emitter.velocity;
+                spawns.push(match emitter.edge {
+                    Edge::Top => (along_x, bounds.y, 0.0, v),
+                    Edge::Left => (bounds.x, along_y, v, 0.0),
+                    Edge::Right => (bounds.x + bounds.width, along_y, -v, 0.0),
+                    Edge::Bottom => (along_x, bounds.y + bounds.height, 0.0, -v),
+                });
+            }
+        }
+        for (x, y, vx, vy) in spawns {
+            if self.insert_particle_with_velocity(x, y, vx, vy).is_none() {
+                break;
+            }
+        }
+    }
+
+    fn cross_portals(&self, rigid_body_set: &mut RigidBodySet, collider_set: &ColliderSet) {
+        let portals = self.portals.lock();
+        let mut arrivals = self.portal_arrivals.lock();
+
+        for crossing in self.events.drain_crossings() {
+            let key = (crossing.portal, crossing.body);
+            if !crossing.entered {
+                arrivals.remove(&key);
+                continue;
+            }
+            if arrivals.contains(&key) {
+                continue;
+            }
+            let Some(exit) = portals.get(&crossing.portal) else {
+                continue;
+            };
+            let (Some(entry_collider), Some(exit_collider)) =
+                (collider_set.get(crossing.portal), collider_set.get(*exit))
+            else {
+                continue;
+            };
+            let Some(body) = rigid_body_set.get_mut(crossing.body) else {
+                continue;
+            };
+            if !body.is_dynamic() {
+                continue;
+            }
+
+            let turn = exit_collider.rotation() * entry_collider.rotation().inverse();
+            let offset = body.translation() - entry_collider.translation();
+            let translation = exit_collider.translation() + turn * offset;
+            let linvel = turn * body.linvel();
+            body.set_translation(translation, true);
+            body.set_linvel(linvel, true);
+            arrivals.insert((*exit, crossing.body));
+        }
+    }
+
+    /* Brings particles whose center has left the box back in past the opposite edge. */
+    fn wrap_edges(&self, rigid_body_set: &mut RigidBodySet, impulse_joint_set: &ImpulseJointSet) {
+        if !self.is_wrapping() {
+            return;
+        }
+        let bounds = self.bounds();
+        let mut trails = self.trails.lock();
+
+        for (handle, body) in rigid_body_set.iter_mut() {
+            /* Chain links stay put, as moving one on its own tears the chain apart. */
+            if !body.is_dynamic() || impulse_joint_set.attached_joints(handle).next().is_some() {
+                continue;
+            }
+            let position = *body.translation();
+            let wrapped = vector![
+                wrap_coordinate(position.x, bounds.x, bounds.width),
+                wrap_coordinate(position.y, bounds.y, bounds.height)
+            ];
+            if wrapped != position {
+                body.set_translation(wrapped, false);
+                /* Its trail would otherwise streak across the whole box. */
+                trails.remove(&handle);
+            }
+        }
+    }
+
+    /* Only the capture holds the sets; the file is written on another thread. */
+    fn autosave_if_due(&self) {
+        let path = {
+            let mut autosave = self.autosave.lock();
+            match autosave.as_mut() {
+                Some(autosave) if autosave.last.elapsed() >= autosave.interval => {
+                    autosave.last = Instant::now();
+                    autosave.path.clone()
+                }
+                _ => return,
+            }
+        };
+
+        let scene = self.capture_scene();
+        std::thread::spawn(move || {
+            if let Err(err) = scene.save_rotating(&path) {
+                eprintln!("autosave to {} failed: {err}", path.display());
+            }
+        });
+    }
+
+    /// Writes one CSV row per dynamic particle, with a header row naming the
+    /// columns `id,x,y,angle,vx,vy,width,height`. Ids are spawn indices, and
+    /// width and height are in the particle's own frame, as in snapshots.
+    pub fn export_csv(&self, path: impl AsRef<Path>) -> Result<(), SandboxError> {
+        let particles = self.snapshots();
+        let rows: Vec<_> = {
+            let rigid_body_set = self.rigid_body_set.lock();
+            particles
+                .iter()
+                .filter_map(|particle| {
+                    let body = rigid_body_set.get(particle.handle?)?;
+                    body.is_dynamic().then(|| (particle, *body.linvel()))
+                })
+                .collect()
+        };
+
+        let mut file = BufWriter::new(std::fs::File::create(path)?);
+        writeln!(file, "id,x,y,angle,vx,vy,width,height")?;
+        for (particle, linvel) in rows {
+            writeln!(
+                file,
+                "{},{},{},{},{},{},{},{}",
+                spawn_index(particle.user_data),
+                particle.x,
+                particle.y,
+                particle.angle,
+                linvel.x,
+                linvel.y,
+                particle.width,
+                particle.height
+            )?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    /* The particles as `capture_scene` sees them, encoded as `Scene::to_text` writes them. */
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.capture_scene().to_text().into_bytes()
+    }
+
+    /* A fresh sandbox with the settings and particles of `to_bytes` output, at the tick they were taken. */
+    pub fn from_bytes(bytes: &[u8]) -> Result<State, SandboxError> {
+        let scene = scene_from_bytes(bytes)?;
+        let state = match scene.config {
+            Some(config) => {
+                let (x, y, width, height) = config.bounds;
+                let mut builder = StateBuilder::default()
+                    .gravity(vector![config.gravity.0, config.gravity.1])
+                    .bounds(width, height)
+                    .step_hz(config.step_hz)
+                    .seed(config.seed)
+                    .target_fps(config.target_fps);
+                if let Some(max_particles) = config.max_particles {
+                    builder = builder.max_particles(max_particles);
+                }
+                let state = builder.build();
+                /* The builder puts the box at the origin. */
+                state.resize(x, y, width, height)?;
+                state
+            }
+            None => State::new(),
+        };
+        state.load_scene(&scene);
+        state.tick.store(scene.tick, Ordering::Relaxed);
+        Ok(state)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SandboxError> {
+        std::fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    /* Adds the particles of a saved scene, returning how many fit under the cap. */
+    pub fn load(&self, path: impl AsRef<Path>) -> Result<usize, SandboxError> {
+        let scene = scene_from_bytes(&std::fs::read(path)?)?;
+        Ok(self.load_scene(&scene))
+    }
+
+    /* Copies every dynamic particle; placed geometry and joints are left out. */
+    pub fn capture_scene(&self) -> Scene {
+        let gravity = self.gravity();
+        let bounds = self.bounds();
+        let config = SavedConfig {
+            gravity: (gravity.x, gravity.y),
+            bounds: (bounds.x, bounds.y, bounds.width, bounds.height),
+            max_particles: self.max_particles,
+            step_hz: self.step_hz,
+            seed: self.rng.lock().resume_seed(),
+            target_fps: self.target_fps,
+        };
+
+        let rigid_body_set = self.rigid_body_set.lock();
+        let collider_set = self.collider_set.lock();
+
+        let bodies = rigid_body_set
+            .iter()
+            .filter(|(_, body)| body.is_dynamic())
+            .filter_map(|(_, body)| {
+                let collider = collider_set.get(*body.colliders().first()?)?;
+                let translation = body.translation();
+                Some(SavedBody {
+                    x: translation.x,
+                    y: translation.y,
+                    angle: body.rotation().angle(),
+                    linvel: (body.linvel().x, body.linvel().y),
+                    angvel: body.angvel(),
+                    user_data: collider.user_data,
+                    friction: collider.friction(),
+                    restitution: collider.restitution(),
+                    shape: SavedShape::from_shape(collider.shape())?,
+                })
+            })
+            .collect();
+
+        Scene {
+            tick: self.tick(),
+            config: Some(config),
+            bodies,
+        }
+    }
+
+    /* Adds the scene's particles to the sandbox, returning how many were inserted. */
+    pub fn load_scene(&self, scene: &Scene) -> usize {
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let mut collider_set = self.collider_set.lock();
+        let mut inserted = 0;
+
+        for saved in &scene.bodies {
+            if self.at_capacity(&rigid_body_set) {
+                break;
+            }
+            let Some(collider) = saved.shape.collider() else {
+                continue;
+            };
+            let mut collider = collider
+                .friction(saved.friction)
+                .restitution(saved.restitution)
+                .active_events(ActiveEvents::COLLISION_EVENTS)
+                .build();
+            collider.user_data = saved.user_data;
+
+            let rigid_body = RigidBodyBuilder::dynamic()
+                .translation(vector![saved.x, saved.y])
+                .rotation(saved.angle)
+                .linvel(vector![saved.linvel.0, saved.linvel.1])
+                .angvel(saved.angvel)
+                .build();
+            let handle = rigid_body_set.insert(rigid_body);
+            collider_set.insert_with_parent(collider, handle, &mut rigid_body_set);
+
+            /* Keep later spawns from reusing the loaded particles' indices and colors. */
+            self.spawn_index
+                .fetch_max(spawn_index(saved.user_data) + 1, Ordering::Relaxed);
+            inserted += 1;
+        }
+        inserted
+    }
+
+    /* Keeps the last `length` positions of each fast particle; 0 turns trails off and drops them. */
+    pub fn set_trail_length(&self, length: usize) {
+        *self.trail_length.lock() = length;
+        if length == 0 {
+            self.trails.lock().clear();
+        }
+    }
+
+    fn record_trails(&self, rigid_body_set: &RigidBodySet, collider_set: &ColliderSet) {
+        let length = *self.trail_length.lock();
+        if length == 0 {
+            return;
+        }
+
+        let mut trails = self.trails.lock();
+        trails.retain(|handle, _| rigid_body_set.contains(*handle));
+        for (handle, body) in rigid_body_set.iter() {
+            if !body.is_dynamic() {
+                continue;
+            }
+            if body.linvel().norm() < TRAIL_MIN_SPEED {
+                /* Let a slowing particle's trail shrink away rather than vanish at once. */
+                if let Some(trail) = trails.get_mut(&handle) {
+                    trail.points.pop_front();
+                }
+                continue;
+            }
+
+            let color = particle_color(
+                body.colliders()
+                    .first()
+                    .and_then(|collider| collider_set.get(*collider))
+                    .map_or(0, |collider| collider.user_data),
+            );
+            let trail = trails.entry(handle).or_insert_with(|| Trail {
+                color,
+                points: VecDeque::with_capacity(length),
+            });
+            /* Follow recoloring, so the tail always matches its particle. */
+            trail.color = color;
+            while trail.points.len() >= length {
+                trail.points.pop_front();
+            }
+            let position = body.translation();
+            trail.points.push_back((position.x, position.y));
+        }
+        trails.retain(|_, trail| !trail.points.is_empty());
+    }
+
+    /* Oldest first; empty if the particle isn't moving fast enough to leave one. */
+    pub fn trail_of(&self, handle: RigidBodyHandle) -> Vec<(f32, f32)> {
+        self.trails
+            .lock()
+            .get(&handle)
+            .map(|trail| trail.points.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /* Each trail with its particle's color, oldest point first. */
+    pub fn for_each_trail(&self, mut func: impl FnMut(&[(f32, f32)], (u8, u8, u8))) {
+        let trails = self.trails.lock();
+        let mut points = Vec::new();
+
+        for trail in trails.values() {
+            points.clear();
+            points.extend(trail.points.iter().copied());
+            func(&points, trail.color);
+        }
+    }
+
+    /* Steps on a plain thread at `step_hz`, sleeping off what each step leaves of its timestep. */
+    pub fn run_physics_thread(self: Arc<Self>) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            let timestep = Duration::from_secs_f32(1.0 / self.step_hz);
+            let mut next = Instant::now();
+            loop {
+                self.step();
+                next += timestep;
+                if self.is_over_budget() {
+                    next += self.step_time();
+                }
+
+                let now = Instant::now();
+                if next > now {
+                    std::thread::sleep(next - now);
+                } else {
+                    /* Running behind: carry on from now rather than bursting to catch up. */
+                    next = now;
+                }
+            }
+        })
+    }
+
+    /// Solves islands across rayon's global thread pool when `true`, and on a
+    /// single thread otherwise, which is the default. Only worth turning on
+    /// for scenes with many thousands of particles spread over many islands;
+    /// small scenes step slower for the coordination.
+    #[cfg(feature = "parallel")]
+    pub fn set_parallel(&self, parallel: bool) {
+        self.parallel.store(parallel, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "parallel")]
+    fn in_step_pool<R: Send>(&self, step: impl FnOnce() -> R + Send) -> R {
+        if self.parallel.load(Ordering::Relaxed) {
+            step()
+        } else {
+            self.serial_pool.install(step)
+        }
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    fn in_step_pool<R>(&self, step: impl FnOnce() -> R) -> R {
+        step()
+    }
+
+    /* `None` stops watching step times. */
+    pub fn set_step_budget(&self, budget: Option<Duration>) {
+        *self.step_budget.lock() = budget;
+        if budget.is_none() {
+            self.over_budget.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /* Whether recent steps ran past the budget; the stepping loop backs off while this holds. */
+    pub fn is_over_budget(&self) -> bool {
+        self.over_budget.load(Ordering::Relaxed)
+    }
+
+    /* Per-step metrics over the last few seconds, for plotting trends. */
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    pub fn step_time(&self) -> Duration {
+        *self.step_time.lock()
+    }
+
+    /// Returns the collisions that started since the last drain.
+    ///
+    /// Contact data is read from the narrow phase at drain time, so it is only
+    /// available for pairs that are still touching; pairs that have already
+    /// separated are left out.
+    pub fn drain_collision_events(&self) -> Vec<Contact> {
+        let narrow_phase = self.narrow_phase.lock();
+        let collider_set = self.collider_set.lock();
+
+        self.events
+            .drain()
+            .into_iter()
+            .filter(|event| event.started())
+            .filter_map(|event| {
+                let pair = narrow_phase.contact_pair(event.collider1(), event.collider2())?;
+                let (manifold, contact) = pair.find_deepest_contact()?;
+                let collider = collider_set.get(pair.collider1)?;
+                Some(Contact {
+                    a: pair.collider1,
+                    b: pair.collider2,
+                    point: collider.position() * contact.local_p1,
+                    normal: manifold.data.normal,
+                    impulse: pair.total_impulse_magnitude(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn contact_count(&self) -> usize {
+        self.narrow_phase
+            .lock()
+            .contact_pairs()
+            .filter(|pair| pair.has_any_active_contact)
+            .count()
+    }
+
+    /* The hardest hit resolved by the last step, as a total contact impulse; `None` while nothing is touching. */
+    pub fn strongest_impact(&self) -> Option<f32> {
+        self.narrow_phase
+            .lock()
+            .contact_pairs()
+            .filter(|pair| pair.has_any_active_contact)
+            .map(|pair| pair.total_impulse_magnitude())
+            .max_by(f32::total_cmp)
+    }
+
+    /* The load on one body: its contacts' impulse magnitudes from the last step, summed; 0 for a stale handle. */
+    pub fn body_impulse(&self, handle: RigidBodyHandle) -> f32 {
+        let narrow_phase = self.narrow_phase.lock();
+        let rigid_body_set = self.rigid_body_set.lock();
+        let Some(body) = rigid_body_set.get(handle) else {
+            return 0.0;
+        };
+        body.colliders()
+            .iter()
+            .flat_map(|collider| narrow_phase.contact_pairs_with(*collider))
+            .filter(|pair| pair.has_any_active_contact)
+            .map(|pair| pair.total_impulse_magnitude())
+            .sum()
+    }
+
+    /// Dynamic bodies touching `handle` from above: ones in active contact
+    /// whose contact normal points upward, within 45 degrees, away from it
+    /// and whose center sits higher than its own. Only direct contact counts;
+    /// bodies stacked on those are found by asking about each in turn.
+    pub fn bodies_resting_on(&self, handle: RigidBodyHandle) -> Vec<RigidBodyHandle> {
+        let narrow_phase = self.narrow_phase.lock();
+        let rigid_body_set = self.rigid_body_set.lock();
+        let collider_set = self.collider_set.lock();
+        let Some(base) = rigid_body_set.get(handle) else {
+            return Vec::new();
+        };
+
+        let mut resting = Vec::new();
+        for collider in base.colliders() {
+            for pair in narrow_phase.contact_pairs_with(*collider) {
+                if !pair.has_any_active_contact {
+                    continue;
+                }
+                /* Manifold normals point from the pair's first collider to its second. */
+                let (other, sign) = if pair.collider1 == *collider {
+                    (pair.collider2, 1.0)
+                } else {
+                    (pair.collider1, -1.0)
+                };
+                let Some(other) = collider_set.get(other).and_then(|c| c.parent()) else {
+                    continue;
+                };
+                let Some(body) = rigid_body_set.get(other) else {
+                    continue;
+                };
+                /* y grows downward, so "up" is negative y. */
+                let upward = pair
+                    .manifolds
+                    .iter()
+                    .filter(|manifold| !manifold.points.is_empty())
+                    .any(|manifold| {
+                        manifold.data.normal.y * sign < -std::f32::consts::FRAC_1_SQRT_2
+                    });
+                if other != handle
+                    && body.is_dynamic()
+                    && upward
+                    && body.translation().y < base.translation().y
+                    && !resting.contains(&other)
+                {
+                    resting.push(other);
+                }
+            }
+        }
+        resting
+    }
+
+    pub fn diagnostics(&self) -> Diagnostics {
+        let contacts = self.contact_count();
+        let rigid_body_set = self.rigid_body_set.lock();
+        let collider_set = self.collider_set.lock();
+        let impulse_joint_set = self.impulse_joint_set.lock();
+        let multibody_joint_set = self.multibody_joint_set.lock();
+
+        let bodies = rigid_body_set.len();
+        let colliders = collider_set.len();
+        let impulse_joints = impulse_joint_set.len();
+        let multibody_joints = multibody_joint_set.iter().count();
+        let awake = rigid_body_set
+            .iter()
+            .filter(|(_, body)| body.is_dynamic() && !body.is_sleeping())
+            .count();
+        let memory_bytes = bodies * std::mem::size_of::<RigidBody>()
+            + colliders * std::mem::size_of::<Collider>()
+            + impulse_joints * std::mem::size_of::<ImpulseJoint>()
+            + multibody_joints * std::mem::size_of::<MultibodyJoint>();
+
+        Diagnostics {
+            bodies,
+            colliders,
+            joints: impulse_joints + multibody_joints,
+            contacts,
+            awake,
+            memory_bytes,
+        }
+    }
+
+    pub fn spawn_settings(&self) -> SpawnSettings {
+        *self.spawn_settings.lock()
+    }
+
+    pub fn set_spawn_size(&self, size: f32) {
+        self.spawn_settings.lock().size = size.clamp(MIN_SPAWN_SIZE, MAX_SPAWN_SIZE);
+    }
+
+    pub fn set_spawn_shape(&self, shape: SpawnShape) {
+        self.spawn_settings.lock().shape = shape;
+    }
+
+    pub fn set_spawn_restitution(&self, restitution: f32) {
+        self.spawn_settings.lock().restitution = restitution.clamp(0.0, 1.0);
+    }
+
+    /* Sleeping spawns drop any starting velocity and stay put until touched or woken. */
+    pub fn set_spawn_asleep(&self, asleep: bool) {
+        self.spawn_settings.lock().asleep = asleep;
+    }
+
+    /* Particles per second emitted by the pour tool. */
+    pub fn pour_rate(&self) -> f32 {
+        *self.pour_rate.lock()
+    }
+
+    pub fn set_pour_rate(&self, pour_rate: f32) {
+        *self.pour_rate.lock() = pour_rate.max(0.0);
+    }
+
+    pub fn grid_snap(&self) -> Option<f32> {
+        *self.grid_snap.lock()
+    }
+
+    pub fn set_grid_snap(&self, grid_snap: Option<f32>) {
+        *self.grid_snap.lock() = grid_snap.filter(|size| *size > 0.0);
+    }
+
+    /* Snaps to the grid (if enabled), then keeps a body of the given half-extents clear of the walls. */
+    fn place(&self, x: f32, y: f32, half_width: f32, half_height: f32) -> (f32, f32) {
+        let (x, y) = match self.grid_snap() {
+            Some(grid) => ((x / grid).round() * grid, (y / grid).round() * grid),
+            None => (x, y),
+        };
+
+        let bounds = self.bounds();
+        let min_x = bounds.x + 1.0 + half_width;
+        let min_y = bounds.y + 1.0 + half_height;
+        let max_x = (bounds.x + bounds.width - 1.0 - half_width).max(min_x);
+        let max_y = (bounds.y + bounds.height - 1.0 - half_height).max(min_y);
+        (x.clamp(min_x, max_x), y.clamp(min_y, max_y))
+    }
+
+    /* Where `insert_current(x, y)` would put the particle, so a preview can be drawn there. */
+    pub fn spawn_position(&self, x: f32, y: f32) -> (f32, f32) {
+        let size = self.spawn_settings().size;
+        self.place(x, y, size, size)
+    }
+
+    pub fn set_position(&self, handle: RigidBodyHandle, x: f32, y: f32) -> bool {
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let collider_set = self.collider_set.lock();
+
+        let Some(body) = rigid_body_set.get_mut(handle) else {
+            return false;
+        };
+        if body
+            .colliders()
+            .iter()
+            .any(|collider| self.is_wall(*collider))
+        {
+            return false;
+        }
+
+        let half_extents = body
+            .colliders()
+            .iter()
+            .filter_map(|collider| collider_set.get(*collider))
+            .map(|collider| collider.compute_aabb().half_extents())
+            .fold(vector![0.0, 0.0], |acc: Vector<Real>, half| acc.sup(&half));
+        let (x, y) = self.place(x, y, half_extents.x, half_extents.y);
+        body.set_translation(vector![x, y], true);
+        true
+    }
+
+    fn next_user_data(&self) -> u128 {
+        let index = self.spawn_index.fetch_add(1, Ordering::Relaxed);
+        let tick = self.tick.load(Ordering::Relaxed) as u32;
+        ((index as u128) << 64) | tick as u128
+    }
+
+    fn at_capacity(&self, rigid_body_set: &RigidBodySet) -> bool {
+        match self.max_particles {
+            Some(max_particles) => {
+                let count = rigid_body_set
+                    .iter()
+                    .filter(|(_, body)| body.is_dynamic())
+                    .count();
+                count >= max_particles
+            }
+            None => false,
+        }
+    }
+
+    /* Returns `None` if the particle cap has been reached. */
+    pub fn insert_particle(&self, x: f32, y: f32) -> Option<RigidBodyHandle> {
+        self.insert_current(x, y)
+    }
+
+    /* Like `insert_current`, but drawn in `rgb` instead of a palette color. */
+    pub fn insert_colored(&self, x: f32, y: f32, rgb: (u8, u8, u8)) -> Option<RigidBodyHandle> {
+        self.spawn_particle(x, y, vector![0.0, 0.0], Some(rgb), InteractionGroups::all())
+    }
+
+    /// Builds `img` out of particles with its top-left at `origin`: every
+    /// `scale`th pixel across and down that isn't fully transparent becomes
+    /// one particle of the current spawn settings in that pixel's color,
+    /// packed a particle's width apart. Past the particle cap the rest of
+    /// the picture is left out, with a warning.
+    pub fn spawn_from_image(
+        &self,
+        img: &RgbaImage,
+        scale: f32,
+        origin: (f32, f32),
+    ) -> Vec<RigidBodyHandle> {
+        let stride = if scale.is_finite() {
+            scale.round().max(1.0) as usize
+        } else {
+            1
+        };
+        let spacing = self.spawn_settings().size * 2.0;
+        let pixels: Vec<_> = (0..img.height())
+            .step_by(stride)
+            .flat_map(|y| (0..img.width()).step_by(stride).map(move |x| (x, y)))
+            .filter_map(|(x, y)| {
+                let [r, g, b, a] = img.pixel(x, y)?;
+                (a > 0).then_some((x / stride, y / stride, (r, g, b)))
+            })
+            .collect();
+
+        if let Some(max_particles) = self.max_particles {
+            let room = max_particles.saturating_sub(self.particle_count());
+            if pixels.len() > room {
+                eprintln!(
+                    "the image needs {} particles but only {room} more fit; leaving out the rest",
+                    pixels.len()
+                );
+            }
+        }
+        pixels
+            .into_iter()
+            .map_while(|(column, row, rgb)| {
+                let x = origin.0 + column as f32 * spacing;
+                let y = origin.1 + row as f32 * spacing;
+                self.insert_colored(x, y, rgb)
+            })
+            .collect()
+    }
+
+    /* Spawns whatever the spawn settings currently describe. */
+    pub fn insert_current(&self, x: f32, y: f32) -> Option<RigidBodyHandle> {
+        self.insert_particle_with_velocity(x, y, 0.0, 0.0)
+    }
+
+    pub fn insert_particle_with_velocity(
+        &self,
+        x: f32,
+        y: f32,
+        vx: f32,
+        vy: f32,
+    ) -> Option<RigidBodyHandle> {
+        self.spawn_particle(x, y, vector![vx, vy], None, InteractionGroups::all())
+    }
+
+    /* Spawns the current particle; `color` and `groups` are set before the collider goes in. */
+    fn spawn_particle(
+        &self,
+        x: f32,
+        y: f32,
+        mut linvel: Vector<Real>,
+        color: Option<(u8, u8, u8)>,
+        groups: InteractionGroups,
+    ) -> Option<RigidBodyHandle> {
+        let settings = self.spawn_settings();
+        let (x, y) = self.spawn_position(x, y);
+        if let Some(max_speed) = *self.max_spawn_speed.lock() {
+            if linvel.norm() > max_speed {
+                linvel = linvel.normalize() * max_speed;
+            }
+        }
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let mut collider_set = self.collider_set.lock();
+
+        if self.at_capacity(&rigid_body_set) {
+            return None;
+        }
+
+        let mut rigid_body = RigidBodyBuilder::dynamic()
+            .translation(vector![x, y])
+            .linvel(linvel)
+            .build();
+
+        if settings.asleep {
+            rigid_body.sleep();
+        } else {
+            rigid_body.wake_up(true);
+        }
+        let mut collider = spawn_collider(settings.shape, settings.size)
+            .restitution(settings.restitution)
+            .active_events(ActiveEvents::COLLISION_EVENTS)
+            .collision_groups(groups)
+            .build();
+
+        collider.user_data = self.next_user_data();
+        if let Some(rgb) = color {
+            collider.user_data = with_color(collider.user_data, rgb);
+        }
+
+        let ball_body_handle = rigid_body_set.insert(rigid_body);
+        collider_set.insert_with_parent(collider, ball_body_handle, &mut rigid_body_set);
+        Some(ball_body_handle)
+    }
+
+    /* Spawns the convex hull of world-space `points`; `None` if they are degenerate or the sandbox is full. */
+    pub fn insert_convex(&self, points: &[(f32, f32)]) -> Option<RigidBodyHandle> {
+        if points.is_empty() {
+            return None;
+        }
+        let count = points.len() as f32;
+        let center = points.iter().fold((0.0, 0.0), |(cx, cy), (x, y)| {
+            (cx + x / count, cy + y / count)
+        });
+        let local: Vec<_> = points
+            .iter()
+            .map(|(x, y)| point![x - center.0, y - center.1])
+            .collect();
+        let mut collider = ColliderBuilder::convex_hull(&local)?
+            .restitution(self.spawn_settings().restitution)
+            .active_events(ActiveEvents::COLLISION_EVENTS)
+            .build();
+
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let mut collider_set = self.collider_set.lock();
+
+        if self.at_capacity(&rigid_body_set) {
+            return None;
+        }
+
+        let rigid_body = RigidBodyBuilder::dynamic()
+            .translation(vector![center.0, center.1])
+            .build();
+        collider.user_data = self.next_user_data();
+
+        let handle = rigid_body_set.insert(rigid_body);
+        collider_set.insert_with_parent(collider, handle, &mut rigid_body_set);
+        Some(handle)
+    }
+
+    /// Inserts each polygon of a level as fixed geometry, one fixed body per
+    /// polygon.
+    ///
+    /// Convex polygons become a single convex-hull collider; concave ones are
+    /// ear-clipped into triangles that share the body and its color. Polygons
+    /// with fewer than three points, or that produce no usable collider, are
+    /// skipped.
+    pub fn load_obstacles_from_points(&self, polygons: &[Vec<(f32, f32)>]) -> Vec<RigidBodyHandle> {
+        let mut handles = Vec::with_capacity(polygons.len());
+
+        for polygon in polygons {
+            if polygon.len() < 3 {
+                continue;
+            }
+            let colliders: Vec<_> = if is_convex(polygon) {
+                let points: Vec<_> = polygon.iter().map(|(x, y)| point![*x, *y]).collect();
+                ColliderBuilder::convex_hull(&points).into_iter().collect()
+            } else {
+                triangulate(polygon)
+                    .into_iter()
+                    .map(|triangle| {
+                        let [a, b, c] = triangle.map(|(x, y)| point![x, y]);
+                        ColliderBuilder::triangle(a, b, c)
+                    })
+                    .collect()
+            };
+            if colliders.is_empty() {
+                continue;
+            }
+
+            let user_data = self.next_user_data();
+            let mut rigid_body_set = self.rigid_body_set.lock();
+            let mut collider_set = self.collider_set.lock();
+            let handle = rigid_body_set.insert(RigidBodyBuilder::fixed().build());
+            for collider in colliders {
+                let mut collider = collider.build();
+                collider.user_data = user_data;
+                collider_set.insert_with_parent(collider, handle, &mut rigid_body_set);
+            }
+            handles.push(handle);
+        }
+        handles
+    }
+
+    /* Hangs `links` beads below a fixed pivot at (x, y), `spacing` apart and free to swing. */
+    pub fn spawn_chain(&self, x: f32, y: f32, links: usize, spacing: f32) -> Vec<RigidBodyHandle> {
+        let spacing = spacing.max(MIN_SPAWN_SIZE * 2.0);
+        let radius = spacing * 0.3;
+
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let mut collider_set = self.collider_set.lock();
+        let mut impulse_joint_set = self.impulse_joint_set.lock();
+
+        let mut parent =
+            rigid_body_set.insert(RigidBodyBuilder::fixed().translation(vector![x, y]).build());
+        let mut handles = Vec::with_capacity(links);
+        for i in 1..=links {
+            if self.at_capacity(&rigid_body_set) {
+                break;
+            }
+
+            let rigid_body = RigidBodyBuilder::dynamic()
+                .translation(vector![x, y + i as f32 * spacing])
+                .build();
+            let mut collider = ColliderBuilder::ball(radius)
+                .active_events(ActiveEvents::COLLISION_EVENTS)
+                .build();
+            collider.user_data = self.next_user_data();
+
+            let handle = rigid_body_set.insert(rigid_body);
+            collider_set.insert_with_parent(collider, handle, &mut rigid_body_set);
+
+            /* The pivot sits halfway between neighbouring beads. */
+            let joint = RevoluteJointBuilder::new()
+                .local_anchor1(point![0.0, spacing / 2.0])
+                .local_anchor2(point![0.0, -spacing / 2.0]);
+            impulse_joint_set.insert(parent, handle, joint, true);
+
+            handles.push(handle);
+            parent = handle;
+        }
+        handles
+    }
+
+    /// Spawns the current particle into collision group `group` (taken mod 32).
+    ///
+    /// Particles in different groups pass through each other; walls, placed
+    /// geometry and ungrouped particles still collide with every group.
+    pub fn insert_in_group(&self, x: f32, y: f32, group: u32) -> Option<RigidBodyHandle> {
+        let bit = Group::from_bits_truncate(1 << (group % 32));
+        self.spawn_particle(
+            x,
+            y,
+            vector![0.0, 0.0],
+            None,
+            InteractionGroups::new(bit, bit),
+        )
+    }
+
+    /* `None` for ungrouped particles and stale handles. */
+    pub fn group_of(&self, handle: RigidBodyHandle) -> Option<u32> {
+        let rigid_body_set = self.rigid_body_set.lock();
+        let collider_set = self.collider_set.lock();
+
+        let collider = collider_set.get(*rigid_body_set.get(handle)?.colliders().first()?)?;
+        collider_group(collider)
+    }
+
+    /* Labels a particle, replacing any tag it had; `false` for stale handles. */
+    pub fn set_tag(&self, handle: RigidBodyHandle, tag: &str) -> bool {
+        let rigid_body_set = self.rigid_body_set.lock();
+        if !rigid_body_set.contains(handle) {
+            return false;
+        }
+        let mut tags = self.tags.lock();
+        /* Bodies removed by the step or `clear` leave their tags behind until now. */
+        tags.retain(|handle, _| rigid_body_set.contains(*handle));
+        tags.insert(handle, tag.to_owned());
+        true
+    }
+
+    pub fn tag_of(&self, handle: RigidBodyHandle) -> Option<String> {
+        let rigid_body_set = self.rigid_body_set.lock();
+        rigid_body_set.get(handle)?;
+        self.tags.lock().get(&handle).cloned()
+    }
+
+    /// Pulls the particles tagged `tag` within `radius` toward `(x, y)`,
+    /// changing each one's velocity by `strength` per call whatever its mass.
+    /// Untagged and differently tagged particles are left alone, so a mixed
+    /// pile can be sorted by dragging this over it. Returns how many were
+    /// pulled.
+    pub fn attract_tagged(&self, x: f32, y: f32, radius: f32, strength: f32, tag: &str) -> usize {
+        let nearby = self.particles_in_radius(x, y, radius);
+
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let tags = self.tags.lock();
+        let mut pulled = 0;
+        for handle in nearby {
+            if tags.get(&handle).map(String::as_str) != Some(tag) {
+                continue;
+            }
+            let Some(body) = rigid_body_set.get_mut(handle) else {
+                continue;
+            };
+            let Some(dir) = (vector![x, y] - body.translation()).try_normalize(f32::EPSILON) else {
+                continue;
+            };
+            body.apply_impulse(dir * strength * body.mass(), true);
+            pulled += 1;
+        }
+        pulled
+    }
+
+    /* Spawns a loose cluster of tiny, grippy grains that pile up like sand. */
+    pub fn insert_sand(&self, x: f32, y: f32, grains: usize) -> Vec<RigidBodyHandle> {
+        const GRAIN_SIZE: f32 = 0.25;
+        const CLUSTER_RADIUS: f32 = 2.0;
+
+        let positions: Vec<_> = (0..grains)
+            .map(|_| {
+                let dx = self.random_range(-CLUSTER_RADIUS, CLUSTER_RADIUS);
+                let dy = self.random_range(-CLUSTER_RADIUS, CLUSTER_RADIUS);
+                self.place(x + dx, y + dy, GRAIN_SIZE, GRAIN_SIZE)
+            })
+            .collect();
+
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let mut collider_set = self.collider_set.lock();
+        let mut handles = Vec::with_capacity(grains);
+
+        for (x, y) in positions {
+            if self.at_capacity(&rigid_body_set) {
+                break;
+            }
+
+            let rigid_body = RigidBodyBuilder::dynamic()
+                .translation(vector![x, y])
+                .build();
+            let mut collider = ColliderBuilder::cuboid(GRAIN_SIZE, GRAIN_SIZE)
+                .restitution(0.0)
+                .friction(0.9)
+                .active_events(ActiveEvents::COLLISION_EVENTS)
+                .build();
+
+            collider.user_data = self.next_user_data();
+
+            let handle = rigid_body_set.insert(rigid_body);
+            collider_set.insert_with_parent(collider, handle, &mut rigid_body_set);
+            handles.push(handle);
+        }
+
+        handles
+    }
+
+    /* Adds a kinematic platform that carries and pushes particles as it swings back and forth. */
+    pub fn add_moving_platform(
+        &self,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        amplitude: f32,
+        period: f32,
+    ) -> RigidBodyHandle {
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let mut collider_set = self.collider_set.lock();
+
+        let rigid_body = RigidBodyBuilder::kinematic_position_based()
+            .translation(vector![x, y])
+            .build();
+        let mut collider = ColliderBuilder::cuboid(width / 2.0, height / 2.0).build();
+
+        collider.user_data = self.next_user_data();
+
+        let handle = rigid_body_set.insert(rigid_body);
+        collider_set.insert_with_parent(collider, handle, &mut rigid_body_set);
+        self.platforms.lock().push(Platform {
+            handle,
+            origin: vector![x, y],
+            amplitude,
+            period: period.max(f32::EPSILON),
+        });
+        handle
+    }
+
+    /* Adds a bucket whose outer box is centered on (x, y); move it with `set_position` and drop it with `remove`. */
+    pub fn add_bucket(
+        &self,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        wall_thickness: f32,
+    ) -> Bucket {
+        let half_width = width.max(wall_thickness * 2.0) / 2.0;
+        let half_height = height.max(wall_thickness) / 2.0;
+        let half_thickness = wall_thickness.max(MIN_SPAWN_SIZE) / 2.0;
+
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let mut collider_set = self.collider_set.lock();
+
+        let rigid_body = RigidBodyBuilder::kinematic_position_based()
+            .translation(vector![x, y])
+            .build();
+        let body = rigid_body_set.insert(rigid_body);
+
+        let user_data = self.next_user_data();
+        let walls = [
+            (
+                vector![-half_width + half_thickness, 0.0],
+                half_thickness,
+                half_height,
+            ),
+            (
+                vector![half_width - half_thickness, 0.0],
+                half_thickness,
+                half_height,
+            ),
+            (
+                vector![0.0, half_height - half_thickness],
+                half_width,
+                half_thickness,
+            ),
+        ]
+        .map(|(offset, half_x, half_y)| {
+            let mut collider = ColliderBuilder::cuboid(half_x, half_y)
+                .translation(offset)
+                .build();
+            collider.user_data = user_data;
+            collider_set.insert_with_parent(collider, body, &mut rigid_body_set)
+        });
+
+        Bucket { body, walls }
+    }
+
+    /// Inserts a fixed polyline through `points`, e.g. a track or ramp.
+    ///
+    /// Polyline segments are one-sided: particles only collide from the side
+    /// the segment normals face, which is the left of the direction of travel
+    /// in screen coordinates (points given left-to-right form a floor).
+    pub fn insert_polyline(&self, points: &[(f32, f32)]) -> ColliderHandle {
+        let vertices = points.iter().map(|(x, y)| point![*x, *y]).collect();
+        let mut collider = ColliderBuilder::polyline(vertices, None).build();
+
+        collider.user_data = self.next_user_data();
+
+        self.collider_set.lock().insert(collider)
+    }
+
+    /* Inserts a zone centered on (x, y) that particles pass through freely, counting each one that enters. */
+    pub fn add_sensor(&self, x: f32, y: f32, w: f32, h: f32) -> SensorHandle {
+        let mut collider = ColliderBuilder::cuboid(w / 2.0, h / 2.0)
+            .translation(vector![x, y])
+            .sensor(true)
+            .active_events(ActiveEvents::COLLISION_EVENTS)
+            .build();
+
+        collider.user_data = self.next_user_data();
+
+        let handle = self.collider_set.lock().insert(collider);
+        self.events.add_sensor(handle);
+        SensorHandle(handle)
+    }
+
+    /* How many distinct particles have entered the sensor; `None` once it's gone. */
+    pub fn sensor_count(&self, sensor: SensorHandle) -> Option<usize> {
+        self.events.sensor_count(sensor.0)
+    }
+
+    /// Places two linked portals. A particle entering either one comes out of
+    /// the other at the same offset, its velocity turned by the difference
+    /// between the portals' orientations. It can't go back through until it
+    /// has left the portal it arrived in.
+    pub fn add_portal_pair(
+        &self,
+        a: (f32, f32),
+        b: (f32, f32),
+    ) -> (ColliderHandle, ColliderHandle) {
+        let [a, b] = [a, b].map(|(x, y)| {
+            let mut collider = ColliderBuilder::ball(PORTAL_RADIUS)
+                .translation(vector![x, y])
+                .sensor(true)
+                .active_events(ActiveEvents::COLLISION_EVENTS)
+                .build();
+            collider.user_data = self.next_user_data();
+
+            let handle = self.collider_set.lock().insert(collider);
+            self.events.add_portal(handle);
+            handle
+        });
+
+        let mut portals = self.portals.lock();
+        portals.insert(a, b);
+        portals.insert(b, a);
+        (a, b)
+    }
+
+    /* Reports each portal's center and radius alongside its partner's center. */
+    pub fn for_each_portal(&self, mut func: impl FnMut((f32, f32), f32, (f32, f32))) {
+        let collider_set = self.collider_set.lock();
+
+        for (portal, partner) in self.portals.lock().iter() {
+            let (Some(portal), Some(partner)) =
+                (collider_set.get(*portal), collider_set.get(*partner))
+            else {
+                continue;
+            };
+            if let TypedShape::Ball(ball) = portal.shape().as_typed_shape() {
+                let (from, to) = (portal.translation(), partner.translation());
+                func((from.x, from.y), ball.radius, (to.x, to.y));
+            }
+        }
+    }
+
+    /* Inserts a fixed belt centered on (x, y) whose surface carries resting particles rightward at `surface_vel`. */
+    pub fn insert_conveyor(
+        &self,
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+        surface_vel: f32,
+    ) -> ColliderHandle {
+        let mut collider = ColliderBuilder::cuboid(w / 2.0, h / 2.0)
+            .translation(vector![x, y])
+            .friction(1.0)
+            .active_hooks(ActiveHooks::MODIFY_SOLVER_CONTACTS)
+            .build();
+
+        collider.user_data = self.next_user_data();
+
+        let handle = self.collider_set.lock().insert(collider);
+        self.hooks.add_conveyor(handle, surface_vel);
+        handle
+    }
+
+    /* A fixed slab centered on (x, y) that particles can rise through from below but land on from above. */
+    pub fn insert_oneway_platform(&self, x: f32, y: f32, w: f32, h: f32) -> ColliderHandle {
+        let mut collider = ColliderBuilder::cuboid(w / 2.0, h / 2.0)
+            .translation(vector![x, y])
+            .active_hooks(ActiveHooks::MODIFY_SOLVER_CONTACTS)
+            .build();
+
+        collider.user_data = self.next_user_data();
+
+        let handle = self.collider_set.lock().insert(collider);
+        self.hooks.add_oneway(handle);
+        handle
+    }
+
+    pub fn particle_at(&self, x: f32, y: f32) -> Option<RigidBodyHandle> {
+        let rigid_body_set = self.rigid_body_set.lock();
+        let collider_set = self.collider_set.lock();
+        let query_pipeline = self.query_pipeline.lock();
+
+        let mut found = None;
+        query_pipeline.intersections_with_point(
+            &rigid_body_set,
+            &collider_set,
+            &point![x, y],
+            QueryFilter::only_dynamic(),
+            |handle| {
+                found = collider_set[handle].parent();
+                false
+            },
+        );
+        found
+    }
+
+    /* The highest dynamic body (smallest y) whose center is within `tolerance` of `x`. */
+    pub fn topmost_at_column(&self, x: f32, tolerance: f32) -> Option<RigidBodyHandle> {
+        self.rigid_body_set
+            .lock()
+            .iter()
+            .filter(|(_, body)| body.is_dynamic())
+            .filter(|(_, body)| (body.translation().x - x).abs() <= tolerance)
+            .min_by(|(_, a), (_, b)| a.translation().y.total_cmp(&b.translation().y))
+            .map(|(handle, _)| handle)
+    }
+
+    /* Every dynamic body with a shape overlapping the circle, each listed once. */
+    pub fn particles_in_radius(&self, x: f32, y: f32, radius: f32) -> Vec<RigidBodyHandle> {
+        if radius.is_nan() || radius <= 0.0 {
+            return Vec::new();
+        }
+
+        let rigid_body_set = self.rigid_body_set.lock();
+        let collider_set = self.collider_set.lock();
+        let query_pipeline = self.query_pipeline.lock();
+        bodies_in_radius(
+            &query_pipeline,
+            &rigid_body_set,
+            &collider_set,
+            point![x, y],
+            radius,
+            usize::MAX,
+        )
+    }
+
+    /* Removes every particle whose shape overlaps the brush circle, not just those centered in it. */
+    pub fn erase_brush(&self, x: f32, y: f32, radius: f32) -> usize {
+        self.particles_in_radius(x, y, radius)
+            .into_iter()
+            .filter(|handle| self.remove(*handle).is_ok())
+            .count()
+    }
+
+    /// Picks up the particle under (x, y) by that point. Dragging steers it
+    /// by setting its velocity each step instead of pulling it with a
+    /// kinematic cursor body, so there is no invisible collider to shove
+    /// its neighbors aside; the particle itself still pushes what it is
+    /// dragged through. Returns `false` if there is nothing there.
+    pub fn begin_drag(&self, x: f32, y: f32) -> bool {
+        let Some(handle) = self.particle_at(x, y) else {
+            return false;
+        };
+        let rigid_body_set = self.rigid_body_set.lock();
+        let Some(body) = rigid_body_set.get(handle) else {
+            return false;
+        };
+        let target = point![x, y];
+        *self.drag.lock() = Some(Drag {
+            handle,
+            anchor: body.position().inverse_transform_point(&target),
+            target,
+        });
+        true
+    }
+
+    pub fn drag_to(&self, x: f32, y: f32) {
+        if let Some(drag) = self.drag.lock().as_mut() {
+            drag.target = point![x, y];
+        }
+    }
+
+    pub fn end_drag(&self) {
+        *self.drag.lock() = None;
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag.lock().is_some()
+    }
+
+    fn apply_drag(&self, rigid_body_set: &mut RigidBodySet) {
+        let mut drag = self.drag.lock();
+        let Some(held) = *drag else {
+            return;
+        };
+        let Some(body) = rigid_body_set.get_mut(held.handle) else {
+            *drag = None;
+            return;
+        };
+
+        let offset = held.target - body.position() * held.anchor;
+        let linvel = offset * DRAG_RESPONSE;
+        let linvel = linvel.cap_magnitude(DRAG_MAX_SPEED);
+        /* Damp the spin so a particle held off-center settles instead of whirling. */
+        let angvel = body.angvel() * 0.9;
+        body.set_linvel(linvel, true);
+        body.set_angvel(angvel, true);
+    }
+
+    /* Returns the first collider hit and the distance along `dir` to it. */
+    pub fn raycast(
+        &self,
+        origin: (f32, f32),
+        dir: (f32, f32),
+        max_toi: f32,
+    ) -> Option<(ColliderHandle, f32)> {
+        let dir = vector![dir.0, dir.1].try_normalize(f32::EPSILON)?;
+        let ray = Ray::new(point![origin.0, origin.1], dir);
+
+        let rigid_body_set = self.rigid_body_set.lock();
+        let collider_set = self.collider_set.lock();
+        let query_pipeline = self.query_pipeline.lock();
+        query_pipeline.cast_ray(
+            &rigid_body_set,
+            &collider_set,
+            &ray,
+            max_toi,
+            true,
+            QueryFilter::default(),
+        )
+    }
+
+    pub fn set_angular_velocity(&self, handle: RigidBodyHandle, omega: f32) -> bool {
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        match rigid_body_set.get_mut(handle) {
+            Some(body) if body.is_dynamic() => {
+                body.set_angvel(omega, true);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /* Calls `func` on every dynamic body under a single lock, e.g. to push them all at once. */
+    pub fn for_each_body_mut(&self, mut func: impl FnMut(&mut RigidBody)) {
+        let mut rigid_body_set = self.rigid_body_set.lock();
+
+        for (_, body) in rigid_body_set.iter_mut() {
+            if body.is_dynamic() {
+                func(body);
+            }
+        }
+    }
+
+    /* Wakes every sleeping particle, e.g. to release an arrangement built with spawns asleep. */
+    pub fn wake_all(&self) {
+        self.for_each_body_mut(|body| body.wake_up(true));
+    }
+
+    pub fn restitution_of(&self, handle: RigidBodyHandle) -> Option<f32> {
+        let rigid_body_set = self.rigid_body_set.lock();
+        let collider_set = self.collider_set.lock();
+
+        let collider = rigid_body_set.get(handle)?.colliders().first()?;
+        Some(collider_set.get(*collider)?.restitution())
+    }
+
+    /* `None` for a stale handle; shape and id come from the body's first collider. */
+    pub fn inspect(&self, handle: RigidBodyHandle) -> Option<ParticleInfo> {
+        let rigid_body_set = self.rigid_body_set.lock();
+        let collider_set = self.collider_set.lock();
+
+        let body = rigid_body_set.get(handle)?;
+        let collider = collider_set.get(*body.colliders().first()?)?;
+        Some(ParticleInfo {
+            id: spawn_index(collider.user_data),
+            position: (body.translation().x, body.translation().y),
+            velocity: (body.linvel().x, body.linvel().y),
+            angular_velocity: body.angvel(),
+            mass: body.mass(),
+            shape: shape_label(collider.shape()),
+            sleeping: body.is_sleeping(),
+        })
+    }
+
+    /* Inserts fixed terrain whose base line is centered on (x, y), with `heights` rising upward evenly across `width`. */
+    pub fn insert_heightfield(
+        &self,
+        x: f32,
+        y: f32,
+        heights: &[f32],
+        width: f32,
+    ) -> ColliderHandle {
+        /* A height field is solid below its heights in local space; rotating it half a turn
+         * puts the solid side down on screen, and reversing the samples undoes the mirroring. */
+        let heights = DVector::from_iterator(heights.len(), heights.iter().rev().copied());
+        let mut collider = ColliderBuilder::heightfield(heights, vector![width, 1.0])
+            .translation(vector![x, y])
+            .rotation(std::f32::consts::PI)
+            .build();
+
+        collider.user_data = self.next_user_data();
+
+        self.collider_set.lock().insert(collider)
+    }
+
+    /// Swaps a particle's collider for `shape`, keeping its size, material,
+    /// color, position and velocity.
+    ///
+    /// The size is the half-extent of the old collider's local bounding box,
+    /// so a cube turns into the ball it fits in. Mass is recomputed from the
+    /// new shape on the next step. Returns `false` for walls, fixed geometry,
+    /// and stale handles.
+    pub fn set_shape(&self, handle: RigidBodyHandle, shape: SpawnShape) -> bool {
+        let mut island_manager = self.island_manager.lock();
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let mut collider_set = self.collider_set.lock();
+
+        let Some(body) = rigid_body_set.get(handle) else {
+            return false;
+        };
+        if !body.is_dynamic() {
+            return false;
+        }
+        let Some(&old) = body.colliders().first() else {
+            return false;
+        };
+        if self.is_wall(old) {
+            return false;
+        }
+
+        let Some(old) = collider_set.remove(old, &mut island_manager, &mut rigid_body_set, true)
+        else {
+            return false;
+        };
+        let extents = old.shape().compute_local_aabb().half_extents();
+        let mut collider = spawn_collider(shape, extents.x.max(extents.y).max(MIN_SPAWN_SIZE))
+            .friction(old.friction())
+            .restitution(old.restitution())
+            .active_events(old.active_events())
+            .build();
+        collider.user_data = old.user_data;
+
+        collider_set.insert_with_parent(collider, handle, &mut rigid_body_set);
+        true
+    }
+
+    /// Scales every particle's size and distance from the box center by
+    /// `factor`, along with its velocity, and recomputes its mass.
+    ///
+    /// Shapes stop shrinking at `MIN_SPAWN_SIZE`, so small factors leave tiny
+    /// particles rather than degenerate ones. Fixed and kinematic geometry is
+    /// left as it is. Non-positive or non-finite factors are ignored.
+    pub fn scale_scene(&self, factor: f32) {
+        if !(factor.is_finite() && factor > 0.0) {
+            return;
+        }
+        let bounds = self.bounds();
+        let center = vector![
+            bounds.x + bounds.width / 2.0,
+            bounds.y + bounds.height / 2.0
+        ];
+
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let mut collider_set = self.collider_set.lock();
+
+        for (_, body) in rigid_body_set.iter_mut() {
+            if !body.is_dynamic() {
+                continue;
+            }
+            for handle in body.colliders() {
+                let Some(collider) = collider_set.get_mut(*handle) else {
+                    continue;
+                };
+                let Some(shape) = SavedShape::from_shape(collider.shape())
+                    .map(|shape| shape.scaled(factor, MIN_SPAWN_SIZE))
+                    .and_then(|shape| shape.collider())
+                else {
+                    continue;
+                };
+                collider.set_shape(shape.build().shared_shape().clone());
+                if let Some(offset) = collider.position_wrt_parent().copied() {
+                    collider.set_translation_wrt_parent(offset.translation.vector * factor);
+                }
+            }
+            body.recompute_mass_properties_from_colliders(&collider_set);
+
+            let translation = center + (body.translation() - center) * factor;
+            body.set_translation(translation, true);
+            body.set_linvel(body.linvel() * factor, true);
+        }
+    }
+
+    /// Kicks every dynamic body in a random direction with an impulse of up
+    /// to `impulse`, waking them all, like shaking the box to loosen a
+    /// wedged pile. Directions come from the seeded generator, so a seeded
+    /// run shakes the same way every time. If the kicks would add more than
+    /// `MAX_SHAKE_ENERGY` in total they are all scaled down to fit.
+    pub fn shake(&self, impulse: f32) {
+        if !impulse.is_finite() || impulse <= 0.0 {
+            return;
+        }
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let mut rng = self.rng.lock();
+
+        let kicks: Vec<_> = rigid_body_set
+            .iter()
+            .filter(|(_, body)| body.is_dynamic() && body.mass() > 0.0)
+            .map(|(handle, _)| {
+                let angle = rng.range(0.0, std::f32::consts::TAU);
+                let magnitude = rng.range(0.0, impulse);
+                (handle, vector![angle.cos(), angle.sin()] * magnitude)
+            })
+            .collect();
+        apply_capped_kicks(&mut rigid_body_set, kicks, MAX_SHAKE_ENERGY);
+    }
+
+    /// Kicks every dynamic body toward the middle of the box, waking them
+    /// all, for a scene-wide collapse. The kick grows with distance from the
+    /// middle: a body at a corner gains `strength` in speed, one halfway out
+    /// half that, so the far edges fall in about as soon as the center.
+    /// Like `shake`, the kicks are scaled down to add at most
+    /// `MAX_IMPLODE_ENERGY` in total.
+    pub fn implode(&self, strength: f32) {
+        if !strength.is_finite() || strength <= 0.0 {
+            return;
+        }
+        let bounds = self.bounds();
+        let center = vector![
+            bounds.x + bounds.width / 2.0,
+            bounds.y + bounds.height / 2.0
+        ];
+        let reach = (vector![bounds.width, bounds.height].norm() / 2.0).max(1.0);
+        let mut rigid_body_set = self.rigid_body_set.lock();
+
+        let kicks: Vec<_> = rigid_body_set
+            .iter()
+            .filter(|(_, body)| body.is_dynamic() && body.mass() > 0.0)
+            .map(|(handle, body)| {
+                let offset = center - body.translation();
+                (handle, offset / reach * strength * body.mass())
+            })
+            .collect();
+        apply_capped_kicks(&mut rigid_body_set, kicks, MAX_IMPLODE_ENERGY);
+    }
+
+    /// Mirrors every particle across the box's vertical center line,
+    /// reversing its horizontal and angular velocity, and wakes it. Fixed
+    /// geometry and chain links stay put, as the chains hang from fixed
+    /// pivots. Shapes are reflected by rotation alone, so lopsided polygons
+    /// come out rotated rather than truly mirrored.
+    pub fn flip_horizontal(&self) {
+        let bounds = self.bounds();
+        let mirror = bounds.x * 2.0 + bounds.width;
+
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let collider_set = self.collider_set.lock();
+        let impulse_joint_set = self.impulse_joint_set.lock();
+        for (handle, body) in rigid_body_set.iter_mut() {
+            if !body.is_dynamic() || impulse_joint_set.attached_joints(handle).next().is_some() {
+                continue;
+            }
+            let Some(collider) = body.colliders().first().and_then(|c| collider_set.get(*c)) else {
+                continue;
+            };
+            /* The collider won't follow the body until the next step, so mirror its box by hand. */
+            let aabb = collider.compute_aabb();
+            let center = point![mirror - aabb.center().x, aabb.center().y];
+            let target = inside_walls(center, aabb.half_extents(), bounds);
+
+            let translation = body.translation();
+            let translation =
+                vector![mirror - translation.x, translation.y] + (target - center.coords);
+            let angle = body.rotation().angle();
+            let linvel = body.linvel();
+            let linvel = vector![-linvel.x, linvel.y];
+            let angvel = body.angvel();
+
+            body.set_translation(translation, true);
+            body.set_rotation(Rotation::new(-angle), true);
+            body.set_linvel(linvel, true);
+            body.set_angvel(-angvel, true);
+        }
+    }
+
+    /// Bakes the given particles into one fixed body with a single compound
+    /// collider made of their shapes, each kept where it was, so an arranged
+    /// structure stops moving for good. The compound takes its material and
+    /// color from the first part. Handles that are stale or not dynamic are
+    /// skipped; `None` if none are left.
+    pub fn weld_selection(&self, handles: &[RigidBodyHandle]) -> Option<RigidBodyHandle> {
+        let mut island_manager = self.island_manager.lock();
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let mut collider_set = self.collider_set.lock();
+        let mut impulse_joint_set = self.impulse_joint_set.lock();
+        let mut multibody_joint_set = self.multibody_joint_set.lock();
+
+        let mut selected: Vec<_> = handles
+            .iter()
+            .copied()
+            .filter(|handle| {
+                rigid_body_set
+                    .get(*handle)
+                    .is_some_and(|body| body.is_dynamic())
+            })
+            .collect();
+        selected.sort_by_key(|handle| handle.into_raw_parts());
+        selected.dedup();
+        if selected.is_empty() {
+            return None;
+        }
+
+        let center = selected
+            .iter()
+            .map(|handle| rigid_body_set[*handle].translation())
+            .sum::<Vector<Real>>()
+            / selected.len() as f32;
+        let origin = Isometry::translation(center.x, center.y);
+
+        let mut parts = Vec::new();
+        let mut material = None;
+        for handle in selected {
+            for collider in rigid_body_set[handle].colliders() {
+                if let Some(collider) = collider_set.get(*collider) {
+                    material.get_or_insert((
+                        collider.friction(),
+                        collider.restitution(),
+                        collider.user_data,
+                    ));
+                    parts.push((
+                        origin.inv_mul(collider.position()),
+                        collider.shared_shape().clone(),
+                    ));
+                }
+            }
+            rigid_body_set.remove(
+                handle,
+                &mut island_manager,
+                &mut collider_set,
+                &mut impulse_joint_set,
+                &mut multibody_joint_set,
+                true,
+            );
+        }
+
+        let welded = rigid_body_set.insert(RigidBodyBuilder::fixed().translation(center));
+        if let Some((friction, restitution, user_data)) = material {
+            let compound = ColliderBuilder::new(SharedShape::compound(parts))
+                .friction(friction)
+                .restitution(restitution)
+                .user_data(user_data)
+                .build();
+            collider_set.insert_with_parent(compound, welded, &mut rigid_body_set);
+        }
+        Some(welded)
+    }
+
+    /// Shatters a dynamic particle into `pieces` equal squares laid out in a
+    /// grid over its former bounds. The pieces split its mass evenly and
+    /// each starts with the velocity the original had at that point, so
+    /// momentum is roughly conserved. Returns the new handles, which fall
+    /// short of `pieces` near the particle cap; empty, with nothing removed,
+    /// if `handle` isn't a dynamic particle.
+    pub fn fragment(&self, handle: RigidBodyHandle, pieces: usize) -> Vec<RigidBodyHandle> {
+        let is_dynamic = self
+            .rigid_body_set
+            .lock()
+            .get(handle)
+            .is_some_and(|body| body.is_dynamic());
+        if pieces == 0 || !is_dynamic {
+            return Vec::new();
+        }
+        let Ok((body, colliders)) = self.take(handle) else {
+            return Vec::new();
+        };
+        let Some(first) = colliders.first() else {
+            return Vec::new();
+        };
+        let aabb = colliders
+            .iter()
+            .map(|collider| collider.compute_aabb())
+            .fold(first.compute_aabb(), |a, b| a.merged(&b));
+        let color = particle_color(first.user_data);
+        let (friction, restitution) = (first.friction(), first.restitution());
+
+        let columns = (pieces as f32).sqrt().ceil() as usize;
+        let rows = pieces.div_ceil(columns);
+        let cell = vector![
+            aabb.extents().x / columns as f32,
+            aabb.extents().y / rows as f32
+        ];
+        let half = (cell.x.min(cell.y) / 2.0).max(MIN_SPAWN_SIZE);
+        let mass = body.mass() / pieces as f32;
+
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let mut collider_set = self.collider_set.lock();
+        let mut spawned = Vec::new();
+        for i in 0..pieces {
+            if self.at_capacity(&rigid_body_set) {
+                break;
+            }
+            let center = point![
+                aabb.mins.x + cell.x * ((i % columns) as f32 + 0.5),
+                aabb.mins.y + cell.y * ((i / columns) as f32 + 0.5)
+            ];
+            let mut piece = RigidBodyBuilder::dynamic()
+                .translation(center.coords)
+                .linvel(body.velocity_at_point(&center))
+                .angvel(body.angvel())
+                .build();
+            piece.wake_up(true);
+            let mut collider = ColliderBuilder::cuboid(half, half)
+                .mass(mass)
+                .friction(friction)
+                .restitution(restitution)
+                .active_events(ActiveEvents::COLLISION_EVENTS)
+                .build();
+            collider.user_data = with_color(self.next_user_data(), color);
+
+            let handle = rigid_body_set.insert(piece);
+            collider_set.insert_with_parent(collider, handle, &mut rigid_body_set);
+            spawned.push(handle);
+        }
+        spawned
+    }
+
+    pub fn remove(&self, handle: RigidBodyHandle) -> Result<(), SandboxError> {
+        self.take(handle).map(|_| ())
+    }
+
+    /* Removes a body and hands back copies of it and its colliders, e.g. for undo. */
+    fn take(&self, handle: RigidBodyHandle) -> Result<(RigidBody, Vec<Collider>), SandboxError> {
+        let mut island_manager = self.island_manager.lock();
+        let mut rigid_body_set = self.rigid_body_set.lock();
         let mut collider_set = self.collider_set.lock();
+        let mut impulse_joint_set = self.impulse_joint_set.lock();
+        let mut multibody_joint_set = self.multibody_joint_set.lock();
+
+        let body = rigid_body_set
+            .get(handle)
+            .ok_or(SandboxError::StaleHandle(handle))?;
+        if body
+            .colliders()
+            .iter()
+            .any(|collider| self.is_wall(*collider))
         {
-            let mut box_top = collider_set.get_mut(*self.box_top.lock()).unwrap();
-            box_top.set_translation(vector![0.0 + x, -9999.0 + y]);
+            return Err(SandboxError::Wall(handle));
+        }
+        let colliders = body
+            .colliders()
+            .iter()
+            .filter_map(|collider| collider_set.get(*collider).cloned())
+            .collect();
+
+        let body = rigid_body_set
+            .remove(
+                handle,
+                &mut island_manager,
+                &mut collider_set,
+                &mut impulse_joint_set,
+                &mut multibody_joint_set,
+                true,
+            )
+            .ok_or(SandboxError::StaleHandle(handle))?;
+        self.trails.lock().remove(&handle);
+        self.tags.lock().remove(&handle);
+        Ok((body, colliders))
+    }
+
+    /* `None` if the sandbox has filled up since the body was taken. */
+    fn restore(&self, body: &RigidBody, colliders: &[Collider]) -> Option<RigidBodyHandle> {
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let mut collider_set = self.collider_set.lock();
+
+        if self.at_capacity(&rigid_body_set) {
+            return None;
+        }
+
+        let handle = rigid_body_set.insert(body.clone());
+        for collider in colliders {
+            collider_set.insert_with_parent(collider.clone(), handle, &mut rigid_body_set);
+        }
+        Some(handle)
+    }
+
+    fn push_undo(&self, action: UndoAction) {
+        let mut undo_stack = self.undo_stack.lock();
+        if undo_stack.len() >= MAX_UNDO {
+            undo_stack.remove(0);
         }
+        undo_stack.push(action);
+    }
+
+    /* Reverts the most recent spawn or removal made through the command queue. */
+    pub fn undo(&self) -> bool {
+        let Some(action) = self.undo_stack.lock().pop() else {
+            return false;
+        };
+
+        match action {
+            UndoAction::Remove(handle) => self.remove(handle).is_ok(),
+            UndoAction::Restore {
+                handle: old_handle,
+                ref body,
+                ref colliders,
+            } => {
+                let Some(handle) = self.restore(body, colliders) else {
+                    /* Leave it on the stack so the undo can be retried once there is room. */
+                    self.undo_stack.lock().push(action);
+                    return false;
+                };
+                /* Earlier entries still refer to the body by the handle it had before removal. */
+                for action in self.undo_stack.lock().iter_mut() {
+                    if let UndoAction::Remove(entry) = action {
+                        if *entry == old_handle {
+                            *entry = handle;
+                        }
+                    }
+                }
+                true
+            }
+        }
+    }
+
+    /* Top, left, bottom, right. */
+    fn walls(&self) -> [ColliderHandle; 4] {
+        [
+            *self.box_top.lock(),
+            *self.box_left.lock(),
+            *self.box_bottom.lock(),
+            *self.box_right.lock(),
+        ]
+    }
+
+    fn is_wall(&self, handle: ColliderHandle) -> bool {
+        handle == *self.box_left.lock()
+            || handle == *self.box_right.lock()
+            || handle == *self.box_top.lock()
+            || handle == *self.box_bottom.lock()
+    }
+
+    pub fn bounds(&self) -> Bounds {
+        *self.bounds.lock()
+    }
+
+    /* Swaps the bottom wall between a giant cuboid and a half-space that nothing can tunnel under. */
+    pub fn set_ground_halfspace(&self, enabled: bool) -> Result<(), SandboxError> {
         {
-            let mut box_left = collider_set.get_mut(*self.box_left.lock()).unwrap();
-            box_left.set_translation(vector![-9999.0 + x, 0.0 + y]);
+            let mut rigid_body_set = self.rigid_body_set.lock();
+            let mut collider_set = self.collider_set.lock();
+            let box_bottom = wall_mut(&mut collider_set, &self.box_bottom)?;
+            let was_halfspace = box_bottom.shape().as_halfspace().is_some();
+            if was_halfspace != enabled {
+                /* The half-space sits 10000 units higher than the cuboid's center for the same
+                 * ground; a gliding bottom wall jumps that rather than sliding it. */
+                let shift = vector![0.0, if enabled { -10000.0 } else { 10000.0 }];
+                if let Some(carrier) = self.wall_carriers.lock().get_mut(2) {
+                    if let Some(body) = rigid_body_set.get_mut(carrier.body) {
+                        let translation = body.translation() + shift;
+                        body.set_translation(translation, false);
+                    }
+                    carrier.target += shift;
+                }
+            }
+            if enabled {
+                box_bottom.set_shape(SharedShape::halfspace(-Vector::y_axis()));
+            } else {
+                box_bottom.set_shape(SharedShape::cuboid(30020.0, 10000.0));
+            }
+        }
+
+        let bounds = self.bounds();
+        self.move_walls(bounds.x, bounds.y, bounds.width, bounds.height)
+    }
+
+    /* Friction and restitution for all four walls; restitution is clamped to [0, 1]. */
+    pub fn set_wall_material(&self, friction: f32, restitution: f32) -> Result<(), SandboxError> {
+        *self.wall_material.lock() = Some((friction.max(0.0), restitution.clamp(0.0, 1.0)));
+
+        let bounds = self.bounds();
+        self.move_walls(bounds.x, bounds.y, bounds.width, bounds.height)
+    }
+
+    /// Joins opposite edges of the box instead of walling them in, for an
+    /// asteroids-style space: the walls stop colliding, and a particle whose
+    /// center leaves one side comes back in at the other with the same
+    /// velocity. Only the center wraps, so a particle straddling an edge is
+    /// drawn on one side until it crosses rather than split across both.
+    pub fn set_wrap(&self, enabled: bool) -> Result<(), SandboxError> {
+        self.wrap.store(enabled, Ordering::Relaxed);
+        let mut collider_set = self.collider_set.lock();
+        for wall in self.walls() {
+            collider_set
+                .get_mut(wall)
+                .ok_or(SandboxError::MissingWall)?
+                .set_enabled(!enabled);
+        }
+        Ok(())
+    }
+
+    pub fn is_wrapping(&self) -> bool {
+        self.wrap.load(Ordering::Relaxed)
+    }
+
+    pub fn wall_move_mode(&self) -> WallMoveMode {
+        if self.wall_carriers.lock().is_empty() {
+            WallMoveMode::Teleport
+        } else {
+            WallMoveMode::Kinematic
         }
+    }
+
+    /* Switching to `Teleport` mid-glide puts the walls straight where they were headed. */
+    pub fn set_wall_move_mode(&self, mode: WallMoveMode) -> Result<(), SandboxError> {
         {
-            let mut box_bottom = collider_set.get_mut(*self.box_bottom.lock()).unwrap();
-            box_bottom.set_translation(vector![0.0 + x, height + 9999.0 + y]);
+            let mut island_manager = self.island_manager.lock();
+            let mut rigid_body_set = self.rigid_body_set.lock();
+            let mut collider_set = self.collider_set.lock();
+            let mut impulse_joint_set = self.impulse_joint_set.lock();
+            let mut multibody_joint_set = self.multibody_joint_set.lock();
+            let mut carriers = self.wall_carriers.lock();
+
+            match mode {
+                WallMoveMode::Kinematic if carriers.is_empty() => {
+                    for wall in self.walls() {
+                        let translation = *collider_set
+                            .get(wall)
+                            .ok_or(SandboxError::MissingWall)?
+                            .translation();
+                        let body = rigid_body_set.insert(
+                            RigidBodyBuilder::kinematic_position_based()
+                                .translation(translation)
+                                .build(),
+                        );
+                        collider_set.set_parent(wall, Some(body), &mut rigid_body_set);
+                        carriers.push(WallCarrier {
+                            body,
+                            target: translation,
+                        });
+                    }
+                }
+                WallMoveMode::Teleport => {
+                    for (wall, carrier) in self.walls().into_iter().zip(carriers.drain(..)) {
+                        collider_set.set_parent(wall, None, &mut rigid_body_set);
+                        rigid_body_set.remove(
+                            carrier.body,
+                            &mut island_manager,
+                            &mut collider_set,
+                            &mut impulse_joint_set,
+                            &mut multibody_joint_set,
+                            false,
+                        );
+                    }
+                }
+                WallMoveMode::Kinematic => {}
+            }
+        }
+
+        let bounds = self.bounds();
+        self.move_walls(bounds.x, bounds.y, bounds.width, bounds.height)
+    }
+
+    /* Heads each gliding wall for its target, at most `MAX_WALL_STEP` this step. */
+    fn glide_walls(&self, rigid_body_set: &mut RigidBodySet) {
+        for carrier in self.wall_carriers.lock().iter() {
+            let Some(body) = rigid_body_set.get_mut(carrier.body) else {
+                continue;
+            };
+            let offset = carrier.target - body.translation();
+            let distance = offset.norm();
+            if distance == 0.0 {
+                continue;
+            }
+            let next = body.translation() + offset * (MAX_WALL_STEP / distance).min(1.0);
+            body.set_next_kinematic_translation(next);
+            body.wake_up(true);
+        }
+    }
+
+    /* Fits the walls to a window at (x, y); ignored while an arena is set. */
+    pub fn resize(&self, x: f32, y: f32, width: f32, height: f32) -> Result<(), SandboxError> {
+        if self.arena().is_some() {
+            return Ok(());
+        }
+        if self.gravity_frame() == GravityFrame::Window {
+            let old = self.bounds();
+            if old.width > 0.0 && old.height > 0.0 && width > 0.0 && height > 0.0 {
+                let gravity = self.gravity();
+                /* Fixed in box-relative coordinates, so it stretches with the box; the strength is kept. */
+                let turned = vector![
+                    gravity.x * width / old.width,
+                    gravity.y * height / old.height
+                ];
+                if let Some(dir) = turned.try_normalize(f32::EPSILON) {
+                    self.set_gravity(dir * gravity.norm());
+                }
+            }
+
+            /* Chain links are left alone, as in `move_walls`: their pivots don't move. */
+            let shift = vector![x - old.x, y - old.y];
+            let mut rigid_body_set = self.rigid_body_set.lock();
+            let impulse_joint_set = self.impulse_joint_set.lock();
+            for (handle, body) in rigid_body_set.iter_mut() {
+                if body.is_dynamic() && impulse_joint_set.attached_joints(handle).next().is_none() {
+                    let translation = body.translation() + shift;
+                    body.set_translation(translation, false);
+                }
+            }
+            /* Gliding walls jump along too, so only a change of size glides. */
+            for carrier in self.wall_carriers.lock().iter() {
+                if let Some(body) = rigid_body_set.get_mut(carrier.body) {
+                    let translation = body.translation() + shift;
+                    body.set_translation(translation, false);
+                }
+            }
+        }
+        self.move_walls(x, y, width, height)
+    }
+
+    pub fn gravity_frame(&self) -> GravityFrame {
+        *self.gravity_frame.lock()
+    }
+
+    pub fn set_gravity_frame(&self, frame: GravityFrame) {
+        *self.gravity_frame.lock() = frame;
+    }
+
+    /// Fixes the walls around a `width` by `height` area with its top-left
+    /// at the world origin, where they stay however the window moves or
+    /// resizes; the window only decides how much of it is visible. Scenes
+    /// recorded in an arena play back in the same space on any screen.
+    pub fn set_arena(&self, width: f32, height: f32) -> Result<(), SandboxError> {
+        *self.arena.lock() = Some((width, height));
+        self.move_walls(0.0, 0.0, width, height)
+    }
+
+    /* Hands the walls back to `resize`, which refits them on its next call. */
+    pub fn clear_arena(&self) {
+        *self.arena.lock() = None;
+    }
+
+    pub fn arena(&self) -> Option<(f32, f32)> {
+        *self.arena.lock()
+    }
+
+    fn move_walls(&self, x: f32, y: f32, width: f32, height: f32) -> Result<(), SandboxError> {
+        *self.bounds.lock() = Bounds {
+            x,
+            y,
+            width,
+            height,
         };
+
+        let mut rigid_body_set = self.rigid_body_set.lock();
+        let mut collider_set = self.collider_set.lock();
+        let bottom_y = if wall_mut(&mut collider_set, &self.box_bottom)?
+            .shape()
+            .as_halfspace()
+            .is_some()
         {
-            let mut box_right = collider_set.get_mut(*self.box_right.lock()).unwrap();
-            box_right.set_translation(vector![width + 9999.0 + x, 0.0 + y]);
+            height - 1.0 + y
+        } else {
+            height + 9999.0 + y
+        };
+        let targets = [
+            vector![0.0 + x, -9999.0 + y],
+            vector![-9999.0 + x, 0.0 + y],
+            vector![0.0 + x, bottom_y],
+            vector![width + 9999.0 + x, 0.0 + y],
+        ];
+
+        /* Scoped so the guard is gone before the joint lock below. */
+        let gliding = {
+            let mut carriers = self.wall_carriers.lock();
+            /* `glide_walls` moves them there over the next steps. */
+            for (carrier, target) in carriers.iter_mut().zip(targets) {
+                carrier.target = target;
+            }
+            !carriers.is_empty()
+        };
+        if !gliding {
+            for (wall, target) in self.walls().into_iter().zip(targets) {
+                collider_set
+                    .get_mut(wall)
+                    .ok_or(SandboxError::MissingWall)?
+                    .set_translation(target);
+            }
+
+            /* Pull particles the walls just closed over back inside instead of leaving them embedded.
+             * Chain links are left alone: moving one on its own tears the chain apart. */
+            let impulse_joint_set = self.impulse_joint_set.lock();
+            for (handle, body) in rigid_body_set.iter_mut() {
+                if !body.is_dynamic() || impulse_joint_set.attached_joints(handle).next().is_some()
+                {
+                    continue;
+                }
+                let Some(collider) = body.colliders().first().and_then(|c| collider_set.get(*c))
+                else {
+                    continue;
+                };
+                let aabb = collider.compute_aabb();
+                let center = aabb.center();
+                let target = inside_walls(center, aabb.half_extents(), self.bounds());
+                if target != center.coords {
+                    let translation = body.translation() + (target - center.coords);
+                    body.set_translation(translation, true);
+                }
+            }
+        }
+
+        if let Some((friction, restitution)) = *self.wall_material.lock() {
+            for wall in self.walls() {
+                if let Some(wall) = collider_set.get_mut(wall) {
+                    /* Take the wall's side of each pair so its material isn't averaged away. */
+                    wall.set_friction(friction);
+                    wall.set_friction_combine_rule(CoefficientCombineRule::Max);
+                    wall.set_restitution(restitution);
+                    wall.set_restitution_combine_rule(CoefficientCombineRule::Max);
+                }
+            }
         }
+        Ok(())
     }
 
-    pub fn for_each_cube(&self, mut func: impl FnMut(f32, f32, f32, f32, u128)) {
-        let collider_set = self.collider_set.lock().clone();
+    /* Copies what the renderer needs under a brief lock, so drawing never stalls `step`. */
+    pub fn snapshots(&self) -> Vec<ParticleSnapshot> {
+        self.snapshots_posed(|_, collider| *collider.position())
+    }
+
+    /// Like `snapshots`, but each moving particle is placed `alpha` of the
+    /// way from where it was before the last step to where it is now, so
+    /// frames drawn between steps move smoothly instead of jumping a whole
+    /// step at a time. `alpha` is clamped to [0, 1]; `step_alpha` gives the
+    /// value for the current moment.
+    pub fn interpolated_snapshots(&self, alpha: f32) -> Vec<ParticleSnapshot> {
+        let alpha = if alpha.is_nan() {
+            1.0
+        } else {
+            alpha.clamp(0.0, 1.0)
+        };
+        /* Copied out, since `step` fills it while holding the collider set. */
+        let previous = self.previous_poses.lock().clone();
+        self.snapshots_posed(|handle, collider| match previous.get(&handle) {
+            Some(before) => before.lerp_slerp(collider.position(), alpha),
+            None => *collider.position(),
+        })
+    }
+
+    /* How far the present is through the current step, as the fraction `interpolated_snapshots` takes. */
+    pub fn step_alpha(&self) -> f32 {
+        (self.last_step.lock().elapsed().as_secs_f32() * self.step_hz).min(1.0)
+    }
+
+    fn snapshots_posed(
+        &self,
+        pose_of: impl Fn(ColliderHandle, &Collider) -> Isometry<Real>,
+    ) -> Vec<ParticleSnapshot> {
+        let collider_set = self.collider_set.lock();
+        let mut snapshots = Vec::with_capacity(collider_set.len());
+        /* Spawn ticks only keep 32 bits, so age is measured modulo that. */
+        let tick = self.tick() as u32;
+        let dt = self.integration_parameters.lock().dt;
 
         for (handle, body) in collider_set.iter() {
-            let pos = body.translation();
-            match body.shape().as_typed_shape() {
-                TypedShape::Ball(b) => todo!(),
-                TypedShape::Cuboid(c) => {
-                    let half_extents = c.half_extents;
-                    func(
-                        pos.x,
-                        pos.y,
-                        half_extents.x * 2.0,
-                        half_extents.y * 2.0,
-                        body.user_data,
-                    );
-                }
-                TypedShape::Capsule(_) => todo!(),
-                TypedShape::Segment(_) => todo!(),
-                TypedShape::Triangle(_) => todo!(),
-                TypedShape::TriMesh(_) => todo!(),
-                TypedShape::Polyline(_) => todo!(),
-                TypedShape::HalfSpace(_) => todo!(),
-                TypedShape::HeightField(_) => todo!(),
-                TypedShape::Compound(_) => todo!(),
-                TypedShape::ConvexPolygon(_) => todo!(),
-                TypedShape::RoundCuboid(_) => todo!(),
-                TypedShape::RoundTriangle(_) => todo!(),
-                TypedShape::RoundConvexPolygon(_) => todo!(),
-                TypedShape::Custom(_) => todo!(),
+            /* The walls are 10000-unit slabs; callers wanting the box draw `bounds()` instead. */
+            if self.is_wall(handle) {
+                continue;
+            }
+            /* Drawn as outlines by for_each_sensor. */
+            if body.is_sensor() {
+                continue;
+            }
+            let pose = pose_of(handle, body);
+            /* A welded compound is drawn one part at a time. */
+            let parts: Vec<_> = match body.shape().as_compound() {
+                Some(compound) => compound
+                    .shapes()
+                    .iter()
+                    .map(|(part_pose, part)| (pose * part_pose, part.as_ref()))
+                    .collect(),
+                None => vec![(pose, body.shape())],
+            };
+            for (pose, part) in parts {
+                let Some((shape, width, height)) = snapshot_shape(part, &pose) else {
+                    continue;
+                };
+                let pos = pose.translation.vector;
+                snapshots.push(ParticleSnapshot {
+                    x: pos.x,
+                    y: pos.y,
+                    width,
+                    height,
+                    angle: pose.rotation.angle(),
+                    shape,
+                    handle: body.parent(),
+                    user_data: body.user_data,
+                    color: particle_color(body.user_data),
+                    restitution: body.restitution(),
+                    age: tick.wrapping_sub(spawn_tick(body.user_data)) as f32 * dt,
+                });
+            }
+        }
+
+        snapshots
+    }
+
+    /* World-space bounding boxes as the broad phase sees them, walls excluded. */
+    pub fn for_each_aabb(&self, mut func: impl FnMut((f32, f32), (f32, f32), u128)) {
+        let aabbs: Vec<_> = {
+            let collider_set = self.collider_set.lock();
+            collider_set
+                .iter()
+                .filter(|(handle, _)| !self.is_wall(*handle))
+                .map(|(_, collider)| (collider.compute_aabb(), collider.user_data))
+                .collect()
+        };
+
+        for (aabb, user_data) in aabbs {
+            func(
+                (aabb.mins.x, aabb.mins.y),
+                (aabb.maxs.x, aabb.maxs.y),
+                user_data,
+            );
+        }
+    }
+
+    pub fn for_each_cube(&self, mut func: impl FnMut(f32, f32, f32, f32, u128)) {
+        for cube in self.snapshots() {
+            if cube.shape == ParticleShape::Cuboid {
+                func(cube.x, cube.y, cube.width, cube.height, cube.user_data);
+            }
+        }
+    }
+
+    /* Reports a point on each plane and its outward normal; the solid side is opposite the normal. */
+    pub fn for_each_halfspace(&self, mut func: impl FnMut((f32, f32), (f32, f32), u128)) {
+        let collider_set = self.collider_set.lock();
+
+        for (_, collider) in collider_set.iter() {
+            if let TypedShape::HalfSpace(halfspace) = collider.shape().as_typed_shape() {
+                let point = collider.translation();
+                let normal = collider.rotation() * *halfspace.normal;
+                func((point.x, point.y), (normal.x, normal.y), collider.user_data);
+            }
+        }
+    }
+
+    /* Reports each height field's surface from left to right on screen. */
+    pub fn for_each_heightfield(&self, mut func: impl FnMut(&[(f32, f32)], u128)) {
+        let collider_set = self.collider_set.lock();
+        let mut points = Vec::new();
+
+        for (_, collider) in collider_set.iter() {
+            if let TypedShape::HeightField(heightfield) = collider.shape().as_typed_shape() {
+                points.clear();
+                for segment in heightfield.segments() {
+                    if points.is_empty() {
+                        let a = collider.position() * segment.a;
+                        points.push((a.x, a.y));
+                    }
+                    let b = collider.position() * segment.b;
+                    points.push((b.x, b.y));
+                }
+                /* The half-turn from insert_heightfield runs the samples right to left. */
+                points.reverse();
+                func(&points, collider.user_data);
+            }
+        }
+    }
+
+    pub fn for_each_polyline(&self, mut func: impl FnMut(&[(f32, f32)], u128)) {
+        let collider_set = self.collider_set.lock();
+        let mut points = Vec::new();
+
+        for (_, collider) in collider_set.iter() {
+            if let TypedShape::Polyline(polyline) = collider.shape().as_typed_shape() {
+                points.clear();
+                points.extend(polyline.vertices().iter().map(|vertex| {
+                    let vertex = collider.position() * vertex;
+                    (vertex.x, vertex.y)
+                }));
+                func(&points, collider.user_data);
+            }
+        }
+    }
+
+    /* Reports each conveyor's center, half extents and surface velocity. */
+    pub fn for_each_conveyor(&self, mut func: impl FnMut((f32, f32), (f32, f32), f32, u128)) {
+        let collider_set = self.collider_set.lock();
+
+        for (handle, surface_vel) in self.hooks.conveyors() {
+            let Some(collider) = collider_set.get(handle) else {
+                continue;
+            };
+            if let TypedShape::Cuboid(cuboid) = collider.shape().as_typed_shape() {
+                let pos = collider.translation();
+                let half_extents = cuboid.half_extents;
+                func(
+                    (pos.x, pos.y),
+                    (half_extents.x, half_extents.y),
+                    surface_vel,
+                    collider.user_data,
+                );
+            }
+        }
+    }
+
+    /* Reports each sensor's center, half extents and count so far. */
+    pub fn for_each_sensor(&self, mut func: impl FnMut((f32, f32), (f32, f32), usize)) {
+        let collider_set = self.collider_set.lock();
+
+        for (handle, count) in self.events.sensors() {
+            let Some(collider) = collider_set.get(handle) else {
+                continue;
+            };
+            if let TypedShape::Cuboid(cuboid) = collider.shape().as_typed_shape() {
+                let pos = collider.translation();
+                let half_extents = cuboid.half_extents;
+                func((pos.x, pos.y), (half_extents.x, half_extents.y), count);
+            }
+        }
+    }
+
+    /* Reports each point the solver is resolving as (x, y), then the contact normal (nx, ny). */
+    pub fn for_each_contact_point(&self, mut func: impl FnMut(f32, f32, f32, f32)) {
+        let narrow_phase = self.narrow_phase.lock();
+
+        for pair in narrow_phase
+            .contact_pairs()
+            .filter(|pair| pair.has_any_active_contact)
+        {
+            for manifold in &pair.manifolds {
+                let normal = manifold.data.normal;
+                for contact in &manifold.data.solver_contacts {
+                    func(contact.point.x, contact.point.y, normal.x, normal.y);
+                }
+            }
+        }
+    }
+
+    /* Reports each joint as its parent body's center, the pivot, and the child body's center. */
+    pub fn for_each_joint(&self, mut func: impl FnMut((f32, f32), (f32, f32), (f32, f32))) {
+        let rigid_body_set = self.rigid_body_set.lock();
+        let impulse_joint_set = self.impulse_joint_set.lock();
+
+        for (_, joint) in impulse_joint_set.iter() {
+            let (Some(parent), Some(child)) = (
+                rigid_body_set.get(joint.body1),
+                rigid_body_set.get(joint.body2),
+            ) else {
+                continue;
+            };
+            let anchor = joint.data.local_frame2.translation.vector;
+            let pivot = child.position() * Point::from(anchor);
+            let (a, b) = (parent.translation(), child.translation());
+            func((a.x, a.y), (pivot.x, pivot.y), (b.x, b.y));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHAPES: [SpawnShape; 4] = [
+        SpawnShape::Cuboid,
+        SpawnShape::Ball,
+        SpawnShape::Capsule,
+        SpawnShape::Triangle,
+    ];
+
+    /* Fixed seeds, so a failure replays the same way every run. */
+    #[test]
+    fn random_operations_never_panic_or_overfill() {
+        const CAP: usize = 40;
+        let state = StateBuilder::default().max_particles(CAP).seed(141).build();
+        let mut rng = Rng::new(141);
+        let mut handles = Vec::new();
+
+        for i in 0..3000 {
+            let bounds = state.bounds();
+            let x = rng.range(bounds.x - 10.0, bounds.x + bounds.width + 10.0);
+            let y = rng.range(bounds.y - 10.0, bounds.y + bounds.height + 10.0);
+            match rng.next_u64() % 12 {
+                0..=2 => {
+                    state.set_spawn_shape(SHAPES[rng.next_u64() as usize % SHAPES.len()]);
+                    state.set_spawn_size(rng.range(0.0, 20.0));
+                    handles.extend(state.insert_current(x, y));
+                }
+                3 => handles.extend(state.insert_sand(x, y, rng.next_u64() as usize % 20)),
+                4 => {
+                    let links = rng.next_u64() as usize % 8;
+                    handles.extend(state.spawn_chain(x, y, links, rng.range(0.0, 10.0)));
+                }
+                5 => {
+                    let points: Vec<_> = (0..3 + rng.next_u64() % 4)
+                        .map(|_| (x + rng.range(-8.0, 8.0), y + rng.range(-8.0, 8.0)))
+                        .collect();
+                    handles.extend(state.insert_convex(&points));
+                }
+                6 => {
+                    /* Handles that were already removed must be refused, not panic. */
+                    if !handles.is_empty() {
+                        let handle = handles[rng.next_u64() as usize % handles.len()];
+                        let _ = state.remove(handle);
+                    }
+                }
+                7 => {
+                    state.enqueue_command(Command::Spawn { x, y });
+                    state.enqueue_command(Command::Remove { x, y });
+                    state.undo();
+                }
+                8 => {
+                    /* Half the resizes collapse the box to no width at all. */
+                    let width = if rng.next_f32() < 0.5 {
+                        0.0
+                    } else {
+                        rng.range(0.0, 400.0)
+                    };
+                    let height = rng.range(0.0, 300.0);
+                    let (x, y) = (rng.range(-50.0, 50.0), rng.range(-50.0, 50.0));
+                    let _ = state.resize(x, y, width, height);
+                }
+                9 => state.set_gravity(vector![rng.range(-30.0, 30.0), rng.range(-30.0, 30.0)]),
+                10 => state.enqueue_command(Command::Impulse {
+                    x,
+                    y,
+                    impulse: vector![rng.range(-500.0, 500.0), rng.range(-500.0, 500.0)],
+                }),
+                _ => state.shake(rng.range(0.0, 50.0)),
+            }
+
+            /* Stepping dominates the run time, so several operations share each step. */
+            if i % 4 == 0 {
+                state.step();
+                state.snapshots();
+            }
+            assert!(state.particle_count() <= CAP);
+        }
+    }
+
+    fn positions(state: &State) -> Vec<(f32, f32)> {
+        state
+            .capture_scene()
+            .bodies
+            .iter()
+            .map(|body| (body.x, body.y))
+            .collect()
+    }
+
+    #[test]
+    fn from_bytes_restores_the_snapshot() {
+        let state = StateBuilder::default()
+            .gravity(vector![2.0, 20.0])
+            .max_particles(30)
+            .step_hz(120.0)
+            .seed(182)
+            .build();
+        state.resize(10.0, 5.0, 200.0, 150.0).unwrap();
+        for i in 0..20 {
+            state.set_spawn_shape(SHAPES[i % SHAPES.len()]);
+            state.insert_current(20.0 + i as f32 * 8.0, 30.0);
+        }
+        for _ in 0..30 {
+            state.step();
+        }
+
+        let bytes = state.to_bytes();
+        let expected = positions(&state);
+        let tick = state.tick();
+        let config = state.capture_scene().config;
+
+        for _ in 0..30 {
+            state.step();
+        }
+        state.insert_current(100.0, 40.0);
+        state.set_gravity(vector![0.0, -9.81]);
+
+        let restored = State::from_bytes(&bytes).unwrap();
+        assert_eq!(positions(&restored), expected);
+        assert_eq!(restored.tick(), tick);
+        assert_eq!(restored.capture_scene().config, config);
+        assert_eq!(restored.max_particles, Some(30));
+    }
+
+    #[test]
+    fn quadratic_drag_caps_falling_speed() {
+        const TERMINAL_SPEED: f32 = 5.0;
+        let state = StateBuilder::default().bounds(320.0, 2000.0).build();
+        let handle = state.insert_current(160.0, 20.0).unwrap();
+        let mass = state.rigid_body_set.lock().get(handle).unwrap().mass();
+        /* Chosen so that `|v| = sqrt(g / k)`, with `k = coeff / mass`, comes out at TERMINAL_SPEED. */
+        let g = state.gravity().norm();
+        state.set_quadratic_drag(mass * g / (TERMINAL_SPEED * TERMINAL_SPEED));
+
+        let speed = |state: &State| {
+            state
+                .rigid_body_set
+                .lock()
+                .get(handle)
+                .unwrap()
+                .linvel()
+                .norm()
+        };
+        for _ in 0..120 {
+            state.step();
+        }
+        let settled = speed(&state);
+        for _ in 0..120 {
+            state.step();
+        }
+        assert!(
+            (settled - TERMINAL_SPEED).abs() < TERMINAL_SPEED * 0.02,
+            "settled at {settled}"
+        );
+        assert!((speed(&state) - settled).abs() < TERMINAL_SPEED * 0.001);
+    }
+
+    /* Prints timings rather than checking anything; run it with
+     * `cargo test --release --features parallel -- --ignored --nocapture`. */
+    #[cfg(feature = "parallel")]
+    #[test]
+    #[ignore]
+    fn serial_vs_parallel_at_10k_particles() {
+        const PARTICLES: usize = 10_000;
+        const STEPS: u32 = 120;
+
+        for parallel in [false, true] {
+            let state = StateBuilder::default().bounds(1000.0, 1000.0).build();
+            for i in 0..PARTICLES {
+                let (column, row) = ((i % 100) as f32, (i / 100) as f32);
+                state.insert_current(5.0 + column * 9.0, 5.0 + row * 9.0);
             }
+            state.set_parallel(parallel);
+
+            let start = Instant::now();
+            for _ in 0..STEPS {
+                state.step();
+            }
+            let mode = if parallel { "parallel" } else { "serial" };
+            println!(
+                "{mode}: {:?} per step over {STEPS} steps",
+                start.elapsed() / STEPS
+            );
+        }
+    }
+
+    #[test]
+    fn weld_selection_makes_one_compound_drawn_per_part() {
+        let state = StateBuilder::default().build();
+        state.set_spawn_shape(SpawnShape::Cuboid);
+        let first = state.insert_current(40.0, 40.0).unwrap();
+        state.set_spawn_shape(SpawnShape::Ball);
+        let second = state.insert_current(60.0, 40.0).unwrap();
+
+        let welded = state.weld_selection(&[first, second]).unwrap();
+        let rigid_body_set = state.rigid_body_set.lock();
+        assert!(rigid_body_set.get(first).is_none());
+        assert_eq!(rigid_body_set[welded].colliders().len(), 1);
+        drop(rigid_body_set);
+
+        let parts: Vec<_> = state
+            .snapshots()
+            .into_iter()
+            .filter(|snapshot| snapshot.handle == Some(welded))
+            .collect();
+        assert_eq!(parts.len(), 2);
+        assert!(matches!(parts[0].shape, ParticleShape::Cuboid));
+        assert!((parts[0].x - 40.0).abs() < 1e-3 && (parts[1].x - 60.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn attract_tagged_leaves_untagged_particles_alone() {
+        let state = StateBuilder::default().gravity(vector![0.0, 0.0]).build();
+        let metal = state.insert_current(40.0, 40.0).unwrap();
+        let glass = state.insert_current(60.0, 40.0).unwrap();
+        let untagged = state.insert_current(40.0, 60.0).unwrap();
+        assert!(state.set_tag(metal, "metal"));
+        assert!(state.set_tag(glass, "glass"));
+        /* The radius query only sees particles once a step has indexed them. */
+        state.step();
+
+        assert_eq!(state.attract_tagged(50.0, 50.0, 40.0, 4.0, "metal"), 1);
+        let rigid_body_set = state.rigid_body_set.lock();
+        assert!(rigid_body_set[metal].linvel().norm() > 0.0);
+        assert_eq!(rigid_body_set[glass].linvel().norm(), 0.0);
+        assert_eq!(rigid_body_set[untagged].linvel().norm(), 0.0);
+        drop(rigid_body_set);
+
+        state.remove(metal).unwrap();
+        assert!(!state.set_tag(metal, "metal"));
+        assert_eq!(state.tag_of(glass).as_deref(), Some("glass"));
+    }
+
+    #[test]
+    fn resize_turns_gravity_only_in_the_window_frame() {
+        let diagonal = vector![10.0, 10.0];
+        let gravity_after_resize = |frame| {
+            let state = StateBuilder::default()
+                .gravity(diagonal)
+                .bounds(100.0, 100.0)
+                .build();
+            state.set_gravity_frame(frame);
+            state.resize(0.0, 0.0, 200.0, 100.0).unwrap();
+            state.gravity()
+        };
+
+        assert_eq!(gravity_after_resize(GravityFrame::World), diagonal);
+        let turned = gravity_after_resize(GravityFrame::Window);
+        /* Still aimed at the bottom-right corner of a box twice as wide. */
+        assert!((turned.x / turned.y - 2.0).abs() < 1e-4);
+        assert!((turned.norm() - diagonal.norm()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn set_shape_keeps_motion_and_recomputes_mass() {
+        let state = StateBuilder::default().gravity(vector![0.0, 0.0]).build();
+        state.set_spawn_shape(SpawnShape::Cuboid);
+        let handle = state.insert_current(80.0, 60.0).unwrap();
+        {
+            let mut rigid_body_set = state.rigid_body_set.lock();
+            rigid_body_set[handle].set_linvel(vector![3.0, -2.0], true);
+            rigid_body_set[handle].set_angvel(1.5, true);
         }
+        let before = state.rigid_body_set.lock()[handle].clone();
+
+        assert!(state.set_shape(handle, SpawnShape::Ball));
+        let rigid_body_set = state.rigid_body_set.lock();
+        let after = &rigid_body_set[handle];
+        assert_eq!(after.translation(), before.translation());
+        assert_eq!(after.linvel(), before.linvel());
+        assert_eq!(after.angvel(), before.angvel());
+        assert!((after.mass() - before.mass()).abs() > 1e-3);
+        drop(rigid_body_set);
+
+        state.remove(handle).unwrap();
+        assert!(!state.set_shape(handle, SpawnShape::Cuboid));
     }
 }